@@ -0,0 +1,302 @@
+//! BC1, BC3 and BC5 block compression of RGBA8 images, for the texture cache.
+//!
+//! Each 4x4 block of pixels is stored as two endpoints and an index per pixel into the colors interpolated
+//! between them: BC1 for colors, BC3 adds a BC4 block for alpha, and BC5 is two BC4 blocks for the red and
+//! green of normal maps. Endpoints are fitted along the principal axis of the block's colors, then refined once.
+
+/// Bytes of a BC1 block, or of one of the halves of a BC3 or BC5 block.
+const HALF_BLOCK_LENGTH: usize = 8;
+
+/// BC1 blocks of `rgba`, alpha is dropped.
+pub fn encode_bc1(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    encode_blocks(rgba, width, height, color_block)
+}
+
+/// BC3 blocks of `rgba`, alpha in a BC4 block followed by the colors in a BC1 block.
+pub fn encode_bc3(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    encode_blocks(rgba, width, height, |pixels, blocks| {
+        single_channel_block(pixels.map(|pixel| pixel[3]), blocks);
+        color_block(pixels, blocks);
+    })
+}
+
+/// BC5 blocks of the red and green of `rgba`.
+pub fn encode_bc5(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    encode_blocks(rgba, width, height, |pixels, blocks| {
+        single_channel_block(pixels.map(|pixel| pixel[0]), blocks);
+        single_channel_block(pixels.map(|pixel| pixel[1]), blocks);
+    })
+}
+
+/// Encode every block of the image, left to right then top to bottom. Levels smaller than a block
+/// repeat their last row and column.
+fn encode_blocks(rgba: &[u8], width: u32, height: u32, encode: impl Fn(&[[u8; 4]; 16], &mut Vec<u8>)) -> Vec<u8> {
+    let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+    let mut blocks = Vec::with_capacity((blocks_x * blocks_y) as usize * 2 * HALF_BLOCK_LENGTH);
+    for block_y in 0..blocks_y {
+        for block_x in 0..blocks_x {
+            let mut pixels = [[0; 4]; 16];
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let x = (block_x * 4 + i as u32 % 4).min(width - 1);
+                let y = (block_y * 4 + i as u32 / 4).min(height - 1);
+                let start = (y * width + x) as usize * 4;
+                pixel.copy_from_slice(&rgba[start..start + 4]);
+            }
+            encode(&pixels, &mut blocks);
+        }
+    }
+    blocks
+}
+
+/// Two RGB565 endpoints, the first one larger so the block uses four colors, and 2-bit indices.
+fn color_block(pixels: &[[u8; 4]; 16], blocks: &mut Vec<u8>) {
+    let colors = pixels.map(|pixel| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]);
+    let (start, end) = principal_endpoints(&colors);
+    let mut fit = fit_colors(&colors, start, end);
+    // one least squares pass over the indices found, kept when it's closer
+    if let Some((start, end)) = refine_endpoints(&colors, fit.1) {
+        let refined = fit_colors(&colors, start, end);
+        if refined.2 < fit.2 {
+            fit = refined;
+        }
+    }
+    let (endpoints, indices, _) = fit;
+    blocks.extend(endpoints[0].to_le_bytes());
+    blocks.extend(endpoints[1].to_le_bytes());
+    blocks.extend(indices.to_le_bytes());
+}
+
+/// The ends of the colors projected on the axis they vary the most along.
+fn principal_endpoints(colors: &[[f32; 3]; 16]) -> ([f32; 3], [f32; 3]) {
+    let mut mean = [0.0; 3];
+    for color in colors {
+        for c in 0..3 {
+            mean[c] += color[c] / 16.0;
+        }
+    }
+    let mut covariance = [[0.0f32; 3]; 3];
+    for color in colors {
+        let d = [color[0] - mean[0], color[1] - mean[1], color[2] - mean[2]];
+        for (row, values) in covariance.iter_mut().enumerate() {
+            for column in 0..3 {
+                values[column] += d[row] * d[column];
+            }
+        }
+    }
+    // power iteration, from the luminance axis
+    let mut axis = [1.0f32, 1.0, 1.0];
+    for _ in 0..8 {
+        let next: [f32; 3] = std::array::from_fn(|row| (0..3).map(|column| covariance[row][column] * axis[column]).sum());
+        let length = next.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            // every pixel has the same color
+            return (mean, mean);
+        }
+        axis = next.map(|value| value / length);
+    }
+    let project = |color: &[f32; 3]| (0..3).map(|c| (color[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (min, max) = colors.iter().map(project).fold((f32::MAX, f32::MIN), |(min, max), t| (min.min(t), max.max(t)));
+    let point = |t: f32| std::array::from_fn(|c| (mean[c] + axis[c] * t).clamp(0.0, 255.0));
+    (point(max), point(min))
+}
+
+/// The endpoints that best fit `colors` with the given indices, solved by least squares.
+fn refine_endpoints(colors: &[[f32; 3]; 16], indices: u32) -> Option<([f32; 3], [f32; 3])> {
+    // weight of the first endpoint for each index
+    const WEIGHTS: [f32; 4] = [1.0, 0.0, 2.0 / 3.0, 1.0 / 3.0];
+    let (mut aa, mut bb, mut ab) = (0.0, 0.0, 0.0);
+    let (mut ax, mut bx) = ([0.0f32; 3], [0.0f32; 3]);
+    for (i, color) in colors.iter().enumerate() {
+        let a = WEIGHTS[(indices >> (2 * i) & 3) as usize];
+        let b = 1.0 - a;
+        aa += a * a;
+        bb += b * b;
+        ab += a * b;
+        for c in 0..3 {
+            ax[c] += a * color[c];
+            bx[c] += b * color[c];
+        }
+    }
+    let determinant = aa * bb - ab * ab;
+    if determinant.abs() < 1e-6 {
+        return None;
+    }
+    let start = std::array::from_fn(|c| ((ax[c] * bb - bx[c] * ab) / determinant).clamp(0.0, 255.0));
+    let end = std::array::from_fn(|c| ((bx[c] * aa - ax[c] * ab) / determinant).clamp(0.0, 255.0));
+    Some((start, end))
+}
+
+/// The RGB565 endpoints of `start` and `end`, the index of the closest palette color for each pixel,
+/// and the squared error of the block.
+fn fit_colors(colors: &[[f32; 3]; 16], start: [f32; 3], end: [f32; 3]) -> ([u16; 2], u32, f32) {
+    let (mut first, mut second) = (to_565(start), to_565(end));
+    if first < second {
+        std::mem::swap(&mut first, &mut second);
+    }
+    if first == second {
+        // a single color, every index points at the first endpoint
+        let color = from_565(first);
+        let error = colors.iter().map(|pixel| distance(pixel, &color)).sum();
+        return ([first, second], 0, error);
+    }
+    let (a, b) = (from_565(first), from_565(second));
+    let palette = [
+        a,
+        b,
+        std::array::from_fn(|c| (2.0 * a[c] + b[c]) / 3.0),
+        std::array::from_fn(|c| (a[c] + 2.0 * b[c]) / 3.0),
+    ];
+    let mut indices = 0;
+    let mut error = 0.0;
+    for (i, pixel) in colors.iter().enumerate() {
+        let (index, distance) = palette
+            .iter()
+            .map(|color| distance(pixel, color))
+            .enumerate()
+            .fold((0, f32::MAX), |best, (index, distance)| if distance < best.1 { (index, distance) } else { best });
+        indices |= (index as u32) << (2 * i);
+        error += distance;
+    }
+    ([first, second], indices, error)
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+fn to_565(color: [f32; 3]) -> u16 {
+    let quantize = |value: f32, max: f32| (value * max / 255.0).round() as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+fn from_565(color: u16) -> [f32; 3] {
+    let (r, g, b) = (color >> 11 & 31, color >> 5 & 63, color & 31);
+    [(r << 3 | r >> 2) as f32, (g << 2 | g >> 4) as f32, (b << 3 | b >> 2) as f32]
+}
+
+/// A BC4 block: the largest and smallest value, with the six values between them, and 3-bit indices.
+fn single_channel_block(values: [u8; 16], blocks: &mut Vec<u8>) {
+    let max = values.iter().copied().max().unwrap_or(0);
+    let min = values.iter().copied().min().unwrap_or(0);
+    blocks.extend([max, min]);
+    if max == min {
+        blocks.extend([0; 6]);
+        return;
+    }
+    let (max, min) = (max as u32, min as u32);
+    let mut palette = [max, min, 0, 0, 0, 0, 0, 0];
+    for (i, value) in palette.iter_mut().enumerate().skip(2) {
+        *value = ((8 - i as u32) * max + (i as u32 - 1) * min + 3) / 7;
+    }
+    let mut indices = 0u64;
+    for (i, value) in values.iter().enumerate() {
+        let index = (0..8).min_by_key(|&index| palette[index].abs_diff(*value as u32)).unwrap_or(0);
+        indices |= (index as u64) << (3 * i);
+    }
+    blocks.extend(&indices.to_le_bytes()[..6]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_color(block: &[u8]) -> [[u8; 3]; 16] {
+        let first = u16::from_le_bytes([block[0], block[1]]);
+        let second = u16::from_le_bytes([block[2], block[3]]);
+        let (a, b) = (from_565(first), from_565(second));
+        let palette: [[f32; 3]; 4] = if first > second {
+            [a, b, std::array::from_fn(|c| (2.0 * a[c] + b[c]) / 3.0), std::array::from_fn(|c| (a[c] + 2.0 * b[c]) / 3.0)]
+        } else {
+            [a, b, std::array::from_fn(|c| (a[c] + b[c]) / 2.0), [0.0; 3]]
+        };
+        let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+        std::array::from_fn(|i| palette[(indices >> (2 * i) & 3) as usize].map(|value| value.round() as u8))
+    }
+
+    fn decode_single_channel(block: &[u8]) -> [u8; 16] {
+        let (max, min) = (block[0] as u32, block[1] as u32);
+        let mut bytes = [0; 8];
+        bytes[..6].copy_from_slice(&block[2..8]);
+        let indices = u64::from_le_bytes(bytes);
+        std::array::from_fn(|i| match indices >> (3 * i) & 7 {
+            0 => max as u8,
+            1 => min as u8,
+            index => (((8 - index as u32) * max + (index as u32 - 1) * min + 3) / 7) as u8,
+        })
+    }
+
+    /// A gradient across red and green, with alpha going the other way.
+    fn gradient(width: u32, height: u32) -> Vec<u8> {
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let value = ((x + y) * 255 / (width + height - 2).max(1)) as u8;
+                rgba.extend([value, value / 2 + 64, 200, 255 - value]);
+            }
+        }
+        rgba
+    }
+
+    /// Pixel `i` of the block at `block_x` of a row of blocks `width` wide.
+    fn source_pixel(rgba: &[u8], width: usize, block_x: usize, i: usize) -> &[u8] {
+        let start = ((i / 4) * width + block_x * 4 + i % 4) * 4;
+        &rgba[start..start + 4]
+    }
+
+    #[test]
+    fn bc1_stays_close_to_the_source() {
+        let rgba = gradient(8, 4);
+        let blocks = encode_bc1(&rgba, 8, 4);
+        assert_eq!(blocks.len(), 2 * 8);
+        for (block_x, block) in blocks.chunks(8).enumerate() {
+            // a block of several colors uses four, which needs the first endpoint to be the larger one
+            assert!(u16::from_le_bytes([block[0], block[1]]) > u16::from_le_bytes([block[2], block[3]]));
+            let pixels: Vec<&[u8]> = (0..16).map(|i| source_pixel(&rgba, 8, block_x, i)).collect();
+            for c in 0..3 {
+                // colors on a line are at most half the distance between palette colors away, plus rounding to 565
+                let range = pixels.iter().map(|pixel| pixel[c]).max().unwrap() - pixels.iter().map(|pixel| pixel[c]).min().unwrap();
+                let tolerance = range / 6 + 4;
+                for (color, pixel) in decode_color(block).iter().zip(&pixels) {
+                    assert!(color[c].abs_diff(pixel[c]) <= tolerance, "{:?} {:?}", color, pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solid_blocks_are_exact() {
+        let rgba = [255, 0, 255, 255].repeat(16);
+        let decoded = decode_color(&encode_bc1(&rgba, 4, 4));
+        assert!(decoded.iter().all(|color| *color == [255, 0, 255]));
+        let blocks = encode_bc5(&[10, 250, 0, 255].repeat(16), 4, 4);
+        assert_eq!(decode_single_channel(&blocks[..8]), [10; 16]);
+        assert_eq!(decode_single_channel(&blocks[8..]), [250; 16]);
+    }
+
+    #[test]
+    fn bc3_and_bc5_keep_alpha_and_green() {
+        let rgba = gradient(4, 4);
+        let bc3 = encode_bc3(&rgba, 4, 4);
+        let bc5 = encode_bc5(&rgba, 4, 4);
+        assert_eq!((bc3.len(), bc5.len()), (16, 16));
+        // at most half the distance between the eight values away
+        for (decoded, c) in [(decode_single_channel(&bc3[..8]), 3), (decode_single_channel(&bc5[8..]), 1)] {
+            let values: Vec<u8> = rgba.chunks(4).map(|pixel| pixel[c]).collect();
+            let tolerance = (values.iter().max().unwrap() - values.iter().min().unwrap()) / 14 + 1;
+            for (decoded, value) in decoded.iter().zip(&values) {
+                assert!(decoded.abs_diff(*value) <= tolerance, "{} {}", decoded, value);
+            }
+        }
+    }
+
+    #[test]
+    fn small_levels_fill_a_block() {
+        let rgba = [[0, 0, 0, 255], [255, 255, 255, 255]].concat();
+        let decoded = decode_color(&encode_bc1(&rgba, 2, 1));
+        assert_eq!(decoded[0], [0, 0, 0]);
+        // past the sides, the last row and column are repeated
+        for (i, color) in decoded.iter().enumerate() {
+            assert_eq!(*color, if i % 4 == 0 { [0, 0, 0] } else { [255, 255, 255] });
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Command line arguments, and the `assetripper.toml` project config holding the same settings.
+//!
+//! Flags win over the config file, which wins over the defaults:
+//!
+//! ```toml
+//! input = "./Assets"
+//! output = "./assets/Assets"
+//! import_before_view = true
+//! threads = 8
+//! output_mode = "hardlink"
+//! shader_profiles = "./shader_profiles.toml"
+//! flip_normal_green = false
+//! texture_cache = true
+//! ```
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+
+use crate::mat_gen::OutputMode;
+
+/// Config file used when `--config` isn't given, if it exists.
+pub const CONFIG_FILE: &str = "./assetripper.toml";
+const DEFAULT_INPUT: &str = "./Assets";
+const DEFAULT_OUTPUT: &str = "./assets/Assets";
+/// Optional user shader profiles, see `shader_profile`.
+const DEFAULT_SHADER_PROFILES: &str = "./shader_profiles.toml";
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Imports the materials of AssetRipper exports and shows them in Bevy")]
+pub struct Cli {
+    /// Project config file [default: ./assetripper.toml]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Root of the AssetRipper export [default: ./Assets]
+    #[arg(long, global = true)]
+    pub input: Option<PathBuf>,
+    /// Where the imported materials are written [default: ./assets/Assets]
+    #[arg(long, global = true)]
+    pub output: Option<PathBuf>,
+    /// Materials imported in parallel [default: number of CPUs]
+    #[arg(long, global = true)]
+    pub threads: Option<usize>,
+    /// copy, hardlink, symlink or manifest-only [default: copy]
+    #[arg(long, global = true)]
+    pub output_mode: Option<OutputMode>,
+    /// Shader profiles file [default: ./shader_profiles.toml]
+    #[arg(long, global = true)]
+    pub shader_profiles: Option<PathBuf>,
+    /// Start the import over when its log is corrupt or from a newer version, instead of failing
+    #[arg(long, global = true)]
+    pub discard_log: bool,
+    /// Flip the green channel of normal maps, for exports whose normal maps have Y down (DirectX),
+    /// `--flip-normal-green=false` turns it off [default: false]
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub flip_normal_green: Option<bool>,
+    /// Write mipmapped KTX2 copies of the textures, which the viewer loads instead,
+    /// `--texture-cache=false` turns it off [default: false]
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub texture_cache: Option<bool>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Import the materials of the export
+    Import {
+        /// Only print what the import would do, and write the plan to this JSON file
+        #[arg(long)]
+        plan: Option<PathBuf>,
+    },
+    /// Show the imported materials, the default command
+    View {
+        /// Import before viewing [default: true]
+        #[arg(long)]
+        import: Option<bool>,
+    },
+    /// Print what the importer makes of a `.mat` file, or the manifest of an imported material folder
+    Inspect { path: PathBuf },
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    import_before_view: Option<bool>,
+    threads: Option<usize>,
+    output_mode: Option<String>,
+    shader_profiles: Option<PathBuf>,
+    flip_normal_green: Option<bool>,
+    texture_cache: Option<bool>,
+}
+
+/// The settings of a run, from the flags, the config file and the defaults.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub import_before_view: bool,
+    pub threads: Option<usize>,
+    pub output_mode: OutputMode,
+    pub shader_profiles: Option<PathBuf>,
+    pub discard_log: bool,
+    pub flip_normal_green: bool,
+    pub texture_cache: bool,
+}
+
+impl Settings {
+    pub fn load(cli: &Cli) -> Result<Settings, Box<dyn Error>> {
+        let config = match &cli.config {
+            Some(path) => read_config(path)?,
+            None if Path::new(CONFIG_FILE).exists() => read_config(Path::new(CONFIG_FILE))?,
+            None => Config::default(),
+        };
+        let config_output_mode = match &config.output_mode {
+            Some(mode) => Some(mode.parse::<OutputMode>()?),
+            None => None,
+        };
+        let import_flag = match &cli.command {
+            Some(Command::View { import }) => *import,
+            _ => None,
+        };
+        let shader_profiles = cli
+            .shader_profiles
+            .clone()
+            .or(config.shader_profiles)
+            .or_else(|| Some(PathBuf::from(DEFAULT_SHADER_PROFILES)).filter(|path| path.exists()));
+        Ok(Settings {
+            input: cli.input.clone().or(config.input).unwrap_or_else(|| PathBuf::from(DEFAULT_INPUT)),
+            output: cli.output.clone().or(config.output).unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT)),
+            import_before_view: import_flag.or(config.import_before_view).unwrap_or(true),
+            threads: cli.threads.or(config.threads),
+            output_mode: cli.output_mode.or(config_output_mode).unwrap_or_default(),
+            shader_profiles,
+            discard_log: cli.discard_log,
+            flip_normal_green: cli.flip_normal_green.or(config.flip_normal_green).unwrap_or(false),
+            texture_cache: cli.texture_cache.or(config.texture_cache).unwrap_or(false),
+        })
+    }
+}
+
+fn read_config(path: &Path) -> Result<Config, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+}
@@ -0,0 +1,172 @@
+//! Size, modification time and blake3 hash of every file of an export, to find what changed between imports.
+//!
+//! Saved next to the import log as a tab separated file, one file per line:
+//!
+//! ```text
+//! <path>\t<size>\t<mtime in ns>\t<blake3 hex>
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::{File, Metadata};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use blake3::{Hash, Hasher};
+use walkdir::WalkDir;
+
+use crate::import_log::replace_file;
+
+pub const FILE_HASHES_FILE_NAME: &str = "hashes.tsv";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileState {
+    pub size: u64,
+    pub mtime: u64,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FileHashes {
+    files: BTreeMap<String, FileState>,
+}
+
+impl FileHashes {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+    /// Hash every file under `directory`. Files whose size and mtime match `previous` keep their old hash
+    /// instead of being read again. Files that can't be read are left out and returned with their error,
+    /// so they count as changed on the next import.
+    pub fn scan<P: AsRef<Path>>(directory: P, previous: &FileHashes) -> (FileHashes, Vec<(PathBuf, io::Error)>) {
+        let directory = directory.as_ref();
+        let mut files = BTreeMap::new();
+        let mut errors = Vec::new();
+        for entry in WalkDir::new(directory) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    errors.push((e.path().unwrap_or(directory).to_path_buf(), e.into()));
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path().to_string_lossy().to_string();
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    errors.push((entry.into_path(), e.into()));
+                    continue;
+                }
+            };
+            let size = metadata.len();
+            let mtime = modified(&metadata);
+            let state = match previous.files.get(&path) {
+                Some(state) if state.size == size && state.mtime == mtime => state.clone(),
+                _ => match hash_file(entry.path()) {
+                    Ok(hash) => FileState { size, mtime, hash: hash.to_hex().to_string() },
+                    Err(e) => {
+                        errors.push((entry.into_path(), e));
+                        continue;
+                    }
+                },
+            };
+            files.insert(path, state);
+        }
+        (FileHashes { files }, errors)
+    }
+    /// Paths that were added, removed or whose content changed since `previous`.
+    pub fn changed_since(&self, previous: &FileHashes) -> BTreeSet<String> {
+        let mut changed: BTreeSet<String> = self
+            .files
+            .iter()
+            .filter(|(path, state)| previous.files.get(*path).map(|old| &old.hash) != Some(&state.hash))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(previous.files.keys().filter(|path| !self.files.contains_key(*path)).cloned());
+        changed
+    }
+    /// A single hash of the whole tree, from the per-file hashes.
+    pub fn digest(&self) -> Hash {
+        let mut hasher = Hasher::new();
+        for (path, state) in &self.files {
+            hasher.update(path.as_bytes());
+            hasher.update(state.hash.as_bytes());
+        }
+        hasher.finalize()
+    }
+
+    /// Replace the hashes at `path`, a crash leaves the old or the new ones whole.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        replace_file(path.as_ref(), |file| {
+            for (path, state) in &self.files {
+                writeln!(file, "{}\t{}\t{}\t{}", path, state.size, state.mtime, state.hash)?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FileHashes, Box<dyn Error>> {
+        let mut files = BTreeMap::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            // split from the right, the path is the only field that could contain a tab
+            let mut fields = line.rsplitn(4, '\t');
+            let (Some(hash), Some(mtime), Some(size), Some(path)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(format!("invalid file hash line: {}", line).into());
+            };
+            files.insert(
+                path.to_string(),
+                FileState {
+                    size: size.parse()?,
+                    mtime: mtime.parse()?,
+                    hash: hash.to_string(),
+                },
+            );
+        }
+        Ok(FileHashes { files })
+    }
+}
+
+/// Modification time in nanoseconds since the epoch, 0 when the platform doesn't have it.
+pub fn modified(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+fn hash_file(path: &Path) -> io::Result<Hash> {
+    let mut hasher = Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_are_reported_not_hashed() {
+        let folder = std::env::temp_dir().join(format!("file_hashes_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("a.mat"), b"material").unwrap();
+
+        let (hashes, errors) = FileHashes::scan(&folder, &FileHashes::default());
+        assert!(errors.is_empty());
+        assert_eq!(hashes.files.len(), 1);
+        assert!(hashes.changed_since(&FileHashes::default()).contains(&folder.join("a.mat").to_string_lossy().to_string()));
+
+        let missing = folder.join("missing");
+        let (empty, errors) = FileHashes::scan(&missing, &hashes);
+        assert!(empty.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, missing);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Reads the JSON chunk of `.glb` files, to find which material each primitive is drawn with
+//! and where the nodes of the scene place the meshes.
+//!
+//! AssetRipper names the glTF materials after the Unity materials, so the names can be matched to `.mat` files.
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use bevy::math::{Mat4, Quat, Vec3};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const JSON_CHUNK_TYPE: &[u8; 4] = b"JSON";
+/// The file header and the header of the JSON chunk.
+const HEADERS_LENGTH: u64 = 20;
+
+/// A primitive of a `.glb` and the name of its material.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GlbPrimitive {
+    /// Index of the mesh, the `N` of Bevy's `MeshN/PrimitiveM` labels.
+    pub mesh: usize,
+    pub primitive: usize,
+    pub material: Option<String>,
+}
+
+/// A node of the `.glb`'s scene drawing a mesh, and its transform in the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlbInstance {
+    pub mesh: usize,
+    pub transform: Mat4,
+}
+
+#[derive(serde::Deserialize)]
+struct Document {
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    materials: Vec<Material>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    scenes: Vec<Scene>,
+    scene: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+struct Mesh {
+    #[serde(default)]
+    primitives: Vec<Primitive>,
+}
+
+#[derive(serde::Deserialize)]
+struct Primitive {
+    material: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+struct Material {
+    name: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Node {
+    mesh: Option<usize>,
+    #[serde(default)]
+    children: Vec<usize>,
+    /// Column-major, used instead of translation, rotation and scale when present.
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+impl Node {
+    fn transform(&self) -> Mat4 {
+        match self.matrix {
+            Some(matrix) => Mat4::from_cols_array(&matrix),
+            None => Mat4::from_scale_rotation_translation(
+                self.scale.map_or(Vec3::ONE, Vec3::from),
+                self.rotation.map_or(Quat::IDENTITY, Quat::from_array),
+                self.translation.map_or(Vec3::ZERO, Vec3::from),
+            ),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Scene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+/// Every primitive of the `.glb` at `path`, with the name of its material.
+pub fn read_primitives<P: AsRef<Path>>(path: P) -> Result<Vec<GlbPrimitive>, Box<dyn Error>> {
+    let document = read_document(path)?;
+    let mut primitives = Vec::new();
+    for (mesh_index, mesh) in document.meshes.iter().enumerate() {
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
+            primitives.push(GlbPrimitive {
+                mesh: mesh_index,
+                primitive: primitive_index,
+                material: primitive
+                    .material
+                    .and_then(|material| document.materials.get(material))
+                    .and_then(|material| material.name.clone()),
+            });
+        }
+    }
+    Ok(primitives)
+}
+
+/// Every node of the default scene of the `.glb` at `path` that draws a mesh, with the transforms of
+/// its parents applied. Files without scenes have their root nodes drawn.
+pub fn read_instances<P: AsRef<Path>>(path: P) -> Result<Vec<GlbInstance>, Box<dyn Error>> {
+    let document = read_document(path)?;
+    let roots: Vec<usize> = match document.scenes.get(document.scene.unwrap_or(0)) {
+        Some(scene) => scene.nodes.clone(),
+        None => {
+            let children: HashSet<usize> = document.nodes.iter().flat_map(|node| node.children.iter().copied()).collect();
+            (0..document.nodes.len()).filter(|node| !children.contains(node)).collect()
+        }
+    };
+    let mut instances = Vec::new();
+    // a node is visited once, so a malformed file with cycles can't loop
+    let mut visited = HashSet::new();
+    let mut stack: Vec<(usize, Mat4)> = roots.into_iter().rev().map(|node| (node, Mat4::IDENTITY)).collect();
+    while let Some((index, parent)) = stack.pop() {
+        let Some(node) = document.nodes.get(index).filter(|_| visited.insert(index)) else {
+            continue;
+        };
+        let transform = parent * node.transform();
+        if let Some(mesh) = node.mesh {
+            instances.push(GlbInstance { mesh, transform });
+        }
+        stack.extend(node.children.iter().rev().map(|&child| (child, transform)));
+    }
+    Ok(instances)
+}
+
+fn read_document<P: AsRef<Path>>(path: P) -> Result<Document, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let file_length = file.metadata()?.len();
+    let mut file = BufReader::new(file);
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != GLB_MAGIC {
+        return Err("not a binary glTF file".into());
+    }
+    let length = u32::from_le_bytes(header[8..12].try_into()?) as u64;
+    // the JSON chunk always comes first
+    let mut chunk_header = [0u8; 8];
+    file.read_exact(&mut chunk_header)?;
+    if &chunk_header[4..8] != JSON_CHUNK_TYPE {
+        return Err("first chunk is not JSON".into());
+    }
+    // the chunk length isn't trusted with the allocation before it is checked against the file
+    let chunk_length = u32::from_le_bytes(chunk_header[0..4].try_into()?) as u64;
+    if HEADERS_LENGTH + chunk_length > length.min(file_length) {
+        return Err("JSON chunk is longer than the file".into());
+    }
+    let mut json = vec![0u8; chunk_length as usize];
+    file.read_exact(&mut json)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `.glb` with `json` as its JSON chunk, the chunk length and total length as given.
+    fn write_glb(name: &str, json: &str, chunk_length: u32, length: u32) -> std::path::PathBuf {
+        let mut data = Vec::new();
+        data.extend(GLB_MAGIC);
+        data.extend(2u32.to_le_bytes());
+        data.extend(length.to_le_bytes());
+        data.extend(chunk_length.to_le_bytes());
+        data.extend(JSON_CHUNK_TYPE);
+        data.extend(json.as_bytes());
+        let path = std::env::temp_dir().join(format!("glb_test_{}_{}.glb", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn read_primitives_reads_materials() {
+        let json = r#"{"meshes":[{"primitives":[{"material":1},{}]}],"materials":[{"name":"M_A"},{"name":"M_B"}]}"#;
+        let path = write_glb("materials", json, json.len() as u32, 20 + json.len() as u32);
+        let primitives = read_primitives(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            primitives,
+            [
+                GlbPrimitive { mesh: 0, primitive: 0, material: Some("M_B".to_string()) },
+                GlbPrimitive { mesh: 0, primitive: 1, material: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_instances_applies_parent_transforms() {
+        let json = r#"{"scene":0,"scenes":[{"nodes":[0]}],"nodes":[
+            {"translation":[1,0,0],"scale":[2,2,2],"children":[1,2]},
+            {"mesh":0,"translation":[0,1,0]},
+            {"mesh":0,"matrix":[1,0,0,0,0,1,0,0,0,0,1,0,0,0,3,1]},
+            {"mesh":1}
+        ]}"#;
+        let path = write_glb("instances", json, json.len() as u32, 20 + json.len() as u32);
+        let instances = read_instances(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the node outside the scene isn't drawn
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].mesh, 0);
+        assert_eq!(instances[0].transform.transform_point3(Vec3::ZERO), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(instances[1].transform.transform_point3(Vec3::ZERO), Vec3::new(1.0, 0.0, 6.0));
+        assert_eq!(instances[1].transform.transform_vector3(Vec3::X), Vec3::new(2.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn read_primitives_rejects_oversized_chunk() {
+        let json = r#"{"meshes":[]}"#;
+        for (name, chunk_length, length) in [("chunk", u32::MAX, u32::MAX), ("header", json.len() as u32, 8)] {
+            let path = write_glb(name, json, chunk_length, length);
+            let result = read_primitives(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(result.is_err());
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Index of every `.meta` file of an export: GUID to asset path, importer type and sub-assets.
+//!
+//! Saved next to the import log as a tab separated file, one asset per line followed by
+//! one tab-indented line per sub-asset:
+//!
+//! ```text
+//! <guid>\t<importer>\t<main fileID>\t<path>
+//! \t<fileID>\t<sub-asset name>
+//! ```
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use serde_yaml::Value;
+
+use crate::import_log::replace_file;
+use crate::unity_yaml::{parse_documents, value_to_i64, value_to_string};
+
+pub const GUID_INDEX_FILE_NAME: &str = "guids.tsv";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GuidEntry {
+    /// Path of the asset, the `.meta` path without its extension.
+    pub path: String,
+    /// Importer block of the meta, e.g. `TextureImporter` or `NativeFormatImporter`.
+    pub importer: String,
+    pub main_file_id: Option<i64>,
+    /// `(fileID, name)` of the objects inside the asset.
+    pub sub_assets: Vec<(i64, String)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GuidIndex {
+    entries: HashMap<String, GuidEntry>,
+    by_path: HashMap<String, String>,
+}
+
+impl GuidIndex {
+    pub fn new() -> GuidIndex {
+        GuidIndex::default()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn insert(&mut self, guid: String, entry: GuidEntry) {
+        self.by_path.insert(entry.path.clone(), guid.clone());
+        self.entries.insert(guid, entry);
+    }
+    pub fn path(&self, guid: &str) -> Option<&str> {
+        self.entries.get(guid).map(|entry| entry.path.as_str())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &GuidEntry)> {
+        self.entries.iter().map(|(guid, entry)| (guid.as_str(), entry))
+    }
+    /// The GUID of the asset at `path`, as it was written into the index.
+    pub fn guid_of<P: AsRef<Path>>(&self, path: P) -> Option<&str> {
+        self.by_path
+            .get(path.as_ref().to_string_lossy().as_ref())
+            .map(String::as_str)
+    }
+
+    /// Read the GUID and importer settings out of the contents of a `.meta` file.
+    pub fn parse_meta(text: &str, path: &str) -> Result<(String, GuidEntry), Box<dyn Error>> {
+        let document = parse_documents(text)?.into_iter().next().ok_or("empty meta file")?;
+        let meta = &document.body;
+        let guid = meta.get("guid").and_then(value_to_string).ok_or("no guid in meta file")?;
+
+        let mut entry = GuidEntry {
+            path: path.to_string(),
+            ..Default::default()
+        };
+        if let Some(mapping) = meta.as_mapping() {
+            if let Some((key, importer)) = mapping
+                .iter()
+                .find(|(key, _)| key.as_str().is_some_and(|key| key.ends_with("Importer")))
+            {
+                entry.importer = value_to_string(key).unwrap_or_default();
+                entry.main_file_id = importer.get("mainObjectFileID").and_then(value_to_i64);
+                entry.sub_assets = sub_assets(importer);
+            }
+        }
+        Ok((guid, entry))
+    }
+
+    /// Replace the index at `path`, a crash leaves the old or the new one whole.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut guids: Vec<&String> = self.entries.keys().collect();
+        guids.sort();
+        replace_file(path.as_ref(), |file| {
+            for guid in guids {
+                let entry = &self.entries[guid];
+                let main_file_id = entry.main_file_id.map(|id| id.to_string()).unwrap_or_default();
+                writeln!(file, "{}\t{}\t{}\t{}", guid, escape(&entry.importer), main_file_id, escape(&entry.path))?;
+                for (file_id, name) in &entry.sub_assets {
+                    writeln!(file, "\t{}\t{}", file_id, escape(name))?;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GuidIndex, Box<dyn Error>> {
+        let file = BufReader::new(std::fs::File::open(path)?);
+        let mut index = GuidIndex::new();
+        let mut current: Option<(String, GuidEntry)> = None;
+        for line in file.lines() {
+            let line = line?;
+            if let Some(sub_asset) = line.strip_prefix('\t') {
+                let (file_id, name) = sub_asset.split_once('\t').ok_or("invalid sub-asset line")?;
+                let (_, entry) = current.as_mut().ok_or("sub-asset before any asset")?;
+                entry.sub_assets.push((file_id.parse()?, unescape(name)));
+                continue;
+            }
+            if let Some((guid, entry)) = current.take() {
+                index.insert(guid, entry);
+            }
+            let fields: Vec<&str> = line.splitn(4, '\t').collect();
+            let [guid, importer, main_file_id, path] = fields[..] else {
+                return Err(format!("invalid guid index line: {}", line).into());
+            };
+            current = Some((
+                guid.to_string(),
+                GuidEntry {
+                    path: unescape(path),
+                    importer: unescape(importer),
+                    main_file_id: main_file_id.parse().ok(),
+                    sub_assets: Vec::new(),
+                },
+            ));
+        }
+        if let Some((guid, entry)) = current {
+            index.insert(guid, entry);
+        }
+        Ok(index)
+    }
+}
+
+/// Sub-assets are listed in `internalIDToNameTable` on Unity 2019.3+, and `fileIDToRecycleName` before.
+fn sub_assets(importer: &Value) -> Vec<(i64, String)> {
+    let mut sub_assets = Vec::new();
+    if let Some(Value::Sequence(table)) = importer.get("internalIDToNameTable") {
+        for item in table {
+            let file_id = item
+                .get("first")
+                .and_then(Value::as_mapping)
+                .and_then(|first| first.values().next())
+                .and_then(value_to_i64);
+            let name = item.get("second").and_then(value_to_string);
+            if let (Some(file_id), Some(name)) = (file_id, name) {
+                sub_assets.push((file_id, name));
+            }
+        }
+    }
+    if let Some(Value::Mapping(table)) = importer.get("fileIDToRecycleName") {
+        for (file_id, name) in table {
+            if let (Some(file_id), Some(name)) = (value_to_i64(file_id), value_to_string(name)) {
+                sub_assets.push((file_id, name));
+            }
+        }
+    }
+    sub_assets
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => output.push('\t'),
+            Some('n') => output.push('\n'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
@@ -0,0 +1,266 @@
+//! The import log, `log.toml` in the output directory: how far the last import got and what its linker found.
+//!
+//! The log carries the version of its layout in `schema`. Logs of an older schema are migrated when read,
+//! so an update of the importer keeps the work of the last import as long as it can still read its log.
+//!
+//! The log is a snapshot, replaced atomically. Materials finished since the last snapshot are appended to
+//! `log.journal`, one source path per line, so a crash loses none of them:
+//!
+//! ```text
+//! <path of the .mat file>
+//! ```
+//!
+//! The linker keeps what it read from every file in `link.journal`, so an interrupted scan continues
+//! with the files it hadn't read yet and an update only reads the files that are new or changed.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::glb::GlbPrimitive;
+use crate::guid_index::GuidEntry;
+use crate::manifest::MeshPrimitive;
+use crate::prefab::MeshBinding;
+
+pub const LOG_FILE_NAME: &str = "log.toml";
+pub const JOURNAL_FILE_NAME: &str = "log.journal";
+pub const LINK_JOURNAL_FILE_NAME: &str = "link.journal";
+/// Version of the log layout. Bump it when a change can't be covered by `#[serde(default)]`,
+/// and add the step from the previous version to `migrate`.
+pub const SCHEMA_VERSION: u32 = 2;
+/// Version of what an import writes into the output directory: the material folders and their manifests.
+/// Bump it when that changes, so the next run imports everything again instead of keeping the old output.
+pub const OUTPUT_VERSION: u32 = 2;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ImportLog {
+    pub schema: u32,
+    pub header: Header,
+    pub step: usize,
+    pub current: usize,
+    /// Materials finished past `current` by other workers.
+    #[serde(default)]
+    pub done_ahead: Vec<usize>,
+    pub total: usize,
+    pub data: Data,
+    /// Schema the log was written with, when it was migrated on read.
+    #[serde(skip)]
+    pub migrated_from: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Header {
+    pub date_time: String,
+    /// Version of the importer that wrote the log.
+    pub version: String,
+    /// `OUTPUT_VERSION` of the importer that wrote the output, 0 for the ones before it.
+    #[serde(default)]
+    pub output_version: u32,
+    pub directory: String,
+    pub completed: bool,
+    #[serde(default)]
+    pub output_mode: String,
+    #[serde(default)]
+    pub flip_normal_green: bool,
+    #[serde(default)]
+    pub texture_cache: bool,
+    /// `ShaderProfiles::digest` of the profiles the import used.
+    #[serde(default)]
+    pub shader_profiles: String,
+    pub hash: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Data {
+    pub materials: Vec<PathBuf>,
+    #[serde(default)]
+    pub meshes: Vec<PathBuf>,
+    #[serde(default)]
+    pub mesh_links: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub used_by: BTreeMap<String, Vec<MeshPrimitive>>,
+}
+
+impl ImportLog {
+    /// Read and migrate the log at `path`, `None` when there is none.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Option<ImportLog>, Box<dyn Error>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut log: toml::Table = toml::from_str(&text)?;
+        let schema = migrate(&mut log)?;
+        let mut log: ImportLog = toml::Value::Table(log).try_into()?;
+        log.migrated_from = Some(schema).filter(|schema| *schema != SCHEMA_VERSION);
+        Ok(Some(log))
+    }
+    /// Replace the log at `path`, so a crash leaves either log whole.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let text = toml::to_string(self)?;
+        replace_file(path.as_ref(), |file| file.write_all(text.as_bytes()))?;
+        Ok(())
+    }
+}
+
+/// Append-only record of the materials finished since the last snapshot of the log.
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Start an empty journal at `path`, the snapshot written before covers everything in the old one.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Journal> {
+        let file = OpenOptions::new().create(true).write(true).truncate(true).open(path.as_ref())?;
+        sync_directory(path.as_ref())?;
+        Ok(Journal { file })
+    }
+    /// Record `material` as finished, on disk by the time this returns.
+    pub fn append(&mut self, material: &Path) -> io::Result<()> {
+        self.file.write_all(format!("{}\n", material.to_string_lossy()).as_bytes())?;
+        self.file.sync_data()
+    }
+    /// The materials in the journal at `path`, empty when there is none.
+    /// A last line cut short by a crash is left out.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let complete = text.rfind('\n').map_or("", |end| &text[..end]);
+        Ok(complete.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+    }
+}
+
+/// What the linker read from a file of the export, with the size and modification time the file had.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ScannedFile {
+    pub path: String,
+    pub size: u64,
+    pub mtime: u64,
+    pub scan: Scan,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Scan {
+    Meta { guid: String, entry: GuidEntry },
+    Prefab(Vec<MeshBinding>),
+    Glb(Vec<GlbPrimitive>),
+}
+
+/// The files the linker read so far: the export directory as a JSON string on the first line,
+/// then one JSON `ScannedFile` per line.
+/// Unlike `Journal` it isn't synced, losing its end only means reading those files again.
+pub struct LinkJournal {
+    file: File,
+}
+
+impl LinkJournal {
+    /// Start the journal at `path` over with the files read from it, dropping a line cut short by a crash.
+    pub fn create<P: AsRef<Path>>(path: P, directory: &str, scanned: &[ScannedFile]) -> io::Result<LinkJournal> {
+        let path = path.as_ref();
+        let mut text = format!("{}\n", serde_json::to_string(directory)?);
+        for scanned in scanned {
+            text.push_str(&serde_json::to_string(scanned)?);
+            text.push('\n');
+        }
+        // replaced through a temporary file, the old journal stays whole until the new one is
+        let temporary = path.with_extension("journal.tmp");
+        std::fs::write(&temporary, text)?;
+        std::fs::rename(&temporary, path)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(LinkJournal { file })
+    }
+    pub fn append(&mut self, scanned: &ScannedFile) -> io::Result<()> {
+        self.file.write_all(format!("{}\n", serde_json::to_string(scanned)?).as_bytes())
+    }
+    /// The files in the journal at `path`, empty when there is none or it was written for another export.
+    /// Everything from a line cut short by a crash on is left out.
+    pub fn read<P: AsRef<Path>>(path: P, directory: &str) -> io::Result<Vec<ScannedFile>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let complete = text.rfind('\n').map_or("", |end| &text[..end]);
+        let mut lines = complete.lines();
+        if lines.next() != Some(serde_json::to_string(directory)?.as_str()) {
+            return Ok(Vec::new());
+        }
+        Ok(lines.map_while(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+/// Replace the file at `path` with what `write` writes, through a temporary file renamed over it
+/// so a crash leaves either the old or the new file whole.
+pub fn replace_file<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let mut file = BufWriter::new(File::create(&temporary)?);
+    write(&mut file)?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&temporary, path)?;
+    sync_directory(path)
+}
+
+/// Make the files written into `folder` and the folder itself survive a power loss.
+pub fn sync_folder(folder: &Path) -> io::Result<()> {
+    for entry in std::fs::read_dir(folder)? {
+        let entry = entry?;
+        // symbolic links are entries of the folder, synced with it
+        if entry.file_type()?.is_file() {
+            File::open(entry.path())?.sync_all()?;
+        }
+    }
+    #[cfg(unix)]
+    File::open(folder)?.sync_all()?;
+    sync_directory(folder)
+}
+
+/// Make a file created or renamed in the directory of `path` survive a power loss.
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Bring `log` up to `SCHEMA_VERSION` one schema at a time, returns the schema it was written with.
+fn migrate(log: &mut toml::Table) -> Result<u32, String> {
+    let schema = match log.get("schema") {
+        // logs written before the schema was recorded
+        None => 1,
+        Some(toml::Value::Integer(schema)) if (1..=SCHEMA_VERSION as i64).contains(schema) => *schema as u32,
+        Some(toml::Value::Integer(schema)) if *schema > SCHEMA_VERSION as i64 => {
+            return Err(format!(
+                "log schema {} was written by a newer version, this one reads up to schema {}",
+                schema, SCHEMA_VERSION
+            ))
+        }
+        Some(schema) => return Err(format!("invalid log schema {}", schema)),
+    };
+    for from in schema..SCHEMA_VERSION {
+        match from {
+            // the GUIDs moved from `data.guids` to `guids.tsv`, imports without it are redone by `check`
+            1 => {
+                if let Some(toml::Value::Table(data)) = log.get_mut("data") {
+                    data.remove("guids");
+                }
+            }
+            _ => unreachable!("no migration from log schema {}", from),
+        }
+    }
+    log.insert("schema".to_string(), toml::Value::Integer(SCHEMA_VERSION as i64));
+    Ok(schema)
+}
@@ -0,0 +1,76 @@
+//! What an import would do, worked out by `Materials::plan` without writing anything.
+use std::error::Error;
+use std::path::Path;
+
+use crate::import_report::write_section;
+use crate::manifest::MaterialManifest;
+
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct ImportPlan {
+    pub output_directory: String,
+    /// How the files would be placed in the material folders, see `OutputMode`.
+    pub output_mode: String,
+    /// The last import completed and nothing changed since, the import would do nothing.
+    pub up_to_date: bool,
+    /// Directories that would be deleted, the whole output directory when starting from scratch.
+    pub deletions: Vec<String>,
+    /// Material folders that would be written, with the files placed in them.
+    pub materials: Vec<PlannedMaterial>,
+    /// Materials left as they are because nothing they use changed.
+    pub unchanged: usize,
+    /// Files that could not be read or parsed, with the reason.
+    pub errors: Vec<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct PlannedMaterial {
+    pub folder: String,
+    /// The manifest the material would get, before textures are baked.
+    pub manifest: MaterialManifest,
+}
+
+impl ImportPlan {
+    /// Human-readable summary, listing at most `max_items` entries per section.
+    pub fn summary(&self, max_items: usize) -> String {
+        if self.up_to_date {
+            return format!("{} is up to date, nothing to do\n", self.output_directory);
+        }
+        let files: usize = self.materials.iter().map(|material| material.manifest.files.len()).sum();
+        let mut text = format!(
+            "Import into {} ({}): {} materials, {} files, {} unchanged\n",
+            self.output_directory,
+            self.output_mode,
+            self.materials.len(),
+            files,
+            self.unchanged
+        );
+        let unresolved: Vec<String> = self
+            .materials
+            .iter()
+            .flat_map(|material| {
+                material
+                    .manifest
+                    .unresolved
+                    .iter()
+                    .map(|(slot, guid)| format!("{}: {} ({})", material.manifest.name, slot, guid))
+            })
+            .collect();
+        let materials: Vec<String> = self
+            .materials
+            .iter()
+            .map(|material| {
+                let mesh = material.manifest.mesh.as_deref().unwrap_or("no mesh");
+                format!("{} ({} files, {})", material.folder, material.manifest.files.len(), mesh)
+            })
+            .collect();
+        write_section(&mut text, "directories to delete", &self.deletions, max_items);
+        write_section(&mut text, "material folders to write", &materials, max_items);
+        write_section(&mut text, "unresolved texture references", &unresolved, max_items);
+        write_section(&mut text, "files skipped because of errors", &self.errors, max_items);
+        text
+    }
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
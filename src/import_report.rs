@@ -0,0 +1,83 @@
+//! What an import could not link or left out, written next to the import log as `import_report.toml`,
+//! with the same in plain text in `import_report.txt`.
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+pub const IMPORT_REPORT_FILE_NAME: &str = "import_report.toml";
+pub const IMPORT_SUMMARY_FILE_NAME: &str = "import_report.txt";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    /// Texture references whose GUID is not in the export.
+    #[serde(default)]
+    pub unresolved_textures: Vec<UnresolvedTexture>,
+    /// Materials no mesh was found for, the viewer shows them on a placeholder.
+    #[serde(default)]
+    pub materials_without_mesh: Vec<String>,
+    /// Textures of the export no imported material uses.
+    #[serde(default)]
+    pub unused_textures: Vec<String>,
+    /// `.glb` files of the export no imported material was matched to.
+    #[serde(default)]
+    pub unused_meshes: Vec<String>,
+    /// Files that could not be read or parsed, with the reason.
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct UnresolvedTexture {
+    pub material: String,
+    pub slot: String,
+    pub guid: String,
+}
+
+impl ImportReport {
+    /// Human-readable summary, listing at most `max_items` entries per section.
+    pub fn summary(&self, max_items: usize) -> String {
+        let mut text = String::new();
+        let mut section = |title: &str, items: Vec<String>| write_section(&mut text, title, &items, max_items);
+        section(
+            "unresolved texture references",
+            self.unresolved_textures
+                .iter()
+                .map(|texture| format!("{}: {} ({})", texture.material, texture.slot, texture.guid))
+                .collect(),
+        );
+        section("materials without a mesh", self.materials_without_mesh.clone());
+        section("unused textures", self.unused_textures.clone());
+        section("unused meshes", self.unused_meshes.clone());
+        section("files skipped because of errors", self.errors.clone());
+        if text.is_empty() {
+            text.push_str("Everything was imported\n");
+        }
+        text
+    }
+
+    /// Write the report and its full summary into `directory`.
+    pub fn write<P: AsRef<Path>>(&self, directory: P) -> Result<(), Box<dyn Error>> {
+        let directory = directory.as_ref();
+        std::fs::write(directory.join(IMPORT_REPORT_FILE_NAME), toml::to_string(self)?)?;
+        std::fs::write(directory.join(IMPORT_SUMMARY_FILE_NAME), self.summary(usize::MAX))?;
+        Ok(())
+    }
+    pub fn read<P: AsRef<Path>>(directory: P) -> Result<ImportReport, Box<dyn Error>> {
+        let text = std::fs::read_to_string(directory.as_ref().join(IMPORT_REPORT_FILE_NAME))?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+/// Append `<count> <title>` and up to `max_items` of `items` to `text`, nothing when there are no items.
+pub fn write_section(text: &mut String, title: &str, items: &[String], max_items: usize) {
+    if items.is_empty() {
+        return;
+    }
+    let _ = writeln!(text, "{} {}", items.len(), title);
+    for item in items.iter().take(max_items) {
+        let _ = writeln!(text, "    {}", item);
+    }
+    if items.len() > max_items {
+        let _ = writeln!(text, "    ... and {} more", items.len() - max_items);
+    }
+}
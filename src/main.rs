@@ -1,19 +1,171 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use bevy::{
+    asset::LoadState,
     prelude::*, 
-    render::camera::Viewport, 
-    window::WindowResized, 
+    render::{
+        camera::Viewport,
+        mesh::VertexAttributeValues,
+        render_resource::{AddressMode, Face, FilterMode, SamplerDescriptor},
+        renderer::RenderDevice,
+        texture::{CompressedImageFormats, ImageSampler},
+    }, 
     winit::WinitSettings,
 };
 use bevy_atmosphere::prelude::*;
 use bevy_spectator::*;
+use clap::Parser;
 
 
+mod block_compression;
+mod cli;
+mod file_hashes;
+mod glb;
+mod guid_index;
+mod import_log;
+mod import_plan;
+mod import_report;
+mod manifest;
 mod mat_gen;
-use crate::mat_gen::Materials;
+mod prefab;
+mod psd;
+mod shader_name;
+mod shader_profile;
+mod texture_bake;
+mod texture_cache;
+mod texture_convert;
+mod texture_settings;
+mod unity_yaml;
+use crate::cli::{Cli, Command, Settings};
+use crate::glb::{GlbInstance, GlbPrimitive};
+use crate::guid_index::{GuidIndex, GUID_INDEX_FILE_NAME};
+use crate::manifest::{MaterialManifest, MaterialProperties, MeshMatch, MeshPrimitive, TextureRef, UvTransform};
+use crate::mat_gen::{ImportStatus, Materials};
+use crate::shader_name::shader_name;
+use crate::shader_profile::ShaderProfiles;
+use crate::texture_settings::{TextureImportSettings, WrapMode};
+use crate::unity_yaml::UnityMaterial;
+
+/// Entries per section of the import report printed after an import, the files have all of them.
+const REPORT_ITEMS: usize = 5;
+
 fn main() {
-    let _ = Materials::from_dir("./Assets", "./assets/Assets").run();
+    let cli = Cli::parse();
+    let settings = match Settings::load(&cli) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let command = cli.command.unwrap_or(Command::View { import: None });
+    let result = match command {
+        Command::Import { plan: Some(plan_file) } => plan_import(&settings, &plan_file),
+        Command::Import { plan: None } => import(&settings).map(|_| ()),
+        Command::View { .. } => {
+            // an interrupted import stops here too
+            let viewing = !settings.import_before_view || import(&settings).unwrap_or_else(|e| {
+                eprintln!("Import failed: {}", e);
+                true
+            });
+            if viewing {
+                view(&settings);
+            }
+            Ok(())
+        }
+        Command::Inspect { path } => inspect(&settings, &path),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn importer(settings: &Settings) -> Result<Materials, Box<dyn Error>> {
+    let mut importer = Materials::from_dir(&settings.input.to_string_lossy(), &settings.output.to_string_lossy());
+    if let Some(shader_profiles) = &settings.shader_profiles {
+        importer.load_shader_profiles(shader_profiles)?;
+    }
+    if let Some(threads) = settings.threads {
+        importer.set_threads(threads);
+    }
+    importer.set_output_mode(settings.output_mode);
+    importer.set_discard_log(settings.discard_log);
+    importer.set_flip_normal_green(settings.flip_normal_green);
+    importer.set_texture_cache(settings.texture_cache);
+    Ok(importer)
+}
+
+/// Import the export, returns `false` when the import was interrupted.
+fn import(settings: &Settings) -> Result<bool, Box<dyn Error>> {
+    let summary = importer(settings)?.run()?;
+    if let Some(report) = &summary.report {
+        print!("{}", report.summary(REPORT_ITEMS));
+    } else if !summary.errors.is_empty() {
+        eprintln!("{} files skipped:", summary.errors.len());
+        for error in &summary.errors {
+            eprintln!("  {}", error);
+        }
+    }
+    Ok(summary.status != ImportStatus::Interrupted)
+}
+
+/// Print what the import would do, and write the full plan as JSON.
+fn plan_import(settings: &Settings, plan_file: &Path) -> Result<(), Box<dyn Error>> {
+    let plan = importer(settings)?.plan()?;
+    print!("{}", plan.summary(REPORT_ITEMS));
+    plan.write_json(plan_file).map_err(|e| format!("{}: {}", plan_file.display(), e))?;
+    Ok(())
+}
+
+/// Print what the importer makes of a `.mat` file, or the manifest of an imported material folder.
+fn inspect(settings: &Settings, path: &Path) -> Result<(), Box<dyn Error>> {
+    if path.is_dir() {
+        let manifest = MaterialManifest::read(path)?;
+        print!("{}", toml::to_string_pretty(&manifest)?);
+        return Ok(());
+    }
+    let material = UnityMaterial::from_file(path)?;
+    let profiles = match &settings.shader_profiles {
+        Some(shader_profiles) => ShaderProfiles::load(shader_profiles)?,
+        None => ShaderProfiles::builtin(),
+    };
+    // GUIDs can only be resolved once the export has been imported
+    let guids = GuidIndex::load(settings.output.join(GUID_INDEX_FILE_NAME)).ok();
+    let shader = shader_name(&material.shader, guids.as_ref().unwrap_or(&GuidIndex::new()));
+    let resolve = |guid: &Option<String>| match (guid, &guids) {
+        (None, _) => "no guid".to_string(),
+        (Some(guid), Some(guids)) => format!("{} ({})", guid, guids.path(guid).unwrap_or("not in the export")),
+        (Some(guid), None) => guid.clone(),
+    };
+    println!("name: {}", material.name);
+    println!("shader: {} {}", shader.as_deref().unwrap_or("unknown"), resolve(&material.shader.guid));
+    println!("profile: {}", profiles.select(&material).name);
+    println!("keywords: {}", material.keywords.join(" "));
+    println!("textures:");
+    for (slot, tex_env) in material.tex_envs.iter().filter(|(_, tex_env)| !tex_env.texture.is_null()) {
+        println!("    {}: {}", slot, resolve(&tex_env.texture.guid));
+    }
+    println!("floats:");
+    for (name, value) in &material.floats {
+        println!("    {}: {}", name, value);
+    }
+    println!("colors:");
+    for (name, [r, g, b, a]) in &material.colors {
+        println!("    {}: {} {} {} {}", name, r, g, b, a);
+    }
+    if guids.is_none() {
+        println!("run import first to resolve GUIDs");
+    }
+    Ok(())
+}
+
+fn view(settings: &Settings) {
+    // Bevy loads absolute paths as they are, everything else is relative to its asset folder
+    let output = std::fs::canonicalize(&settings.output).unwrap_or_else(|_| settings.output.clone());
     App::new()
     .insert_resource(AtmosphereModel::new(Gradient{
         sky: Color::rgb_u8(135, 206, 235),
@@ -21,11 +173,37 @@ fn main() {
         ground: Color::rgb_u8(135, 206, 235),
     }))
     .insert_resource(WinitSettings::desktop_app())
+    .insert_resource(Guids(
+        GuidIndex::load(output.join(GUID_INDEX_FILE_NAME)).unwrap_or_default(),
+    ))
+    .insert_resource(MaterialFolders::read(output.join("assets")))
+    .insert_resource(OutputDirectory(output))
+    .init_resource::<PendingTextureSettings>()
+    .init_resource::<SelectedMaterial>()
+    .init_resource::<FlippedNormals>()
     .add_systems(Startup, setup)
     .add_systems(Update,spin)
     .add_systems(Update, scroll)
     .add_systems(Update, change_asset)
-    .add_plugins((DefaultPlugins, AtmospherePlugin, SpectatorPlugin))
+    .add_systems(Update, apply_uv_transform)
+    .add_systems(Update, apply_texture_settings)
+    .add_systems(Update, toggle_normal_flip)
+    .add_plugins((
+        // Unity textures repeat by default, which tiled materials rely on
+        DefaultPlugins.set(ImagePlugin {
+            default_sampler: SamplerDescriptor {
+                address_mode_u: AddressMode::Repeat,
+                address_mode_v: AddressMode::Repeat,
+                address_mode_w: AddressMode::Repeat,
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                mipmap_filter: FilterMode::Linear,
+                ..Default::default()
+            },
+        }),
+        AtmospherePlugin,
+        SpectatorPlugin,
+    ))
 
     .run();
  
@@ -37,6 +215,7 @@ fn setup(
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
     window: Query<&Window>,
+    output: Res<OutputDirectory>,
 
 ) {
     // Load the texture files
@@ -49,29 +228,38 @@ fn setup(
         normal_map_texture: Some(normal.clone()),
         metallic_roughness_texture: Some(orm.clone()),
         occlusion_texture: Some(orm.clone()),
-        unlit: true,
     
         ..Default::default()
     });
     let grid = asset_server.load("textures/Grid.jpg");
 
-    // Spawn a mesh with the material
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            ..Default::default()
+        },
+        transform: Transform::from_xyz(4.0, 8.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..Default::default()
+    });
+
+    // Spawn a mesh with the material, every primitive is a child of the spinning entity
     commands
-        .spawn((PbrBundle {
-            mesh: asset_server.load("mesh/SM_Apple_01_A.glb#Mesh0/Primitive0"),
-            material: material.clone(),
+        .spawn((SpatialBundle {
             transform: Transform::from_scale(Vec3::splat(5.0)),
             ..Default::default()
         },
         Spin{},
         AssetData {
-            mesh: "mesh/SM_Apple_01_A.glb#Mesh0/Primitive0".to_string(),
-            albedo: "textures/T_Apple_01_A_ALB.png".to_string(),
-            normal: "textures/T_Apple_01_A_NRM.png".to_string(),
-            orm: "textures/T_Apple_01_A_ORM.png".to_string(),
-            material_id: material.clone(),
+            mesh: "mesh/SM_Apple_01_A.glb".to_string(),
         }
-    ));
+    ))
+    .with_children(|parent| {
+        parent.spawn(PbrBundle {
+            mesh: asset_server.load("mesh/SM_Apple_01_A.glb#Mesh0/Primitive0"),
+            material: material.clone(),
+            ..Default::default()
+        });
+    });
     
 
     commands.spawn((
@@ -88,10 +276,10 @@ fn setup(
     ));
     // create ui in left third of screen
     // a scrollable list of different assets, Vec<(PATH, NAME)>
-    let mut list: Vec<(String, String)> = std::fs::read_dir("./assets/Assets/assets").unwrap().map(|x| {
-        let path = x.unwrap().path();
-        let name = path.file_name().unwrap().to_str().unwrap().to_string();
-        (path.to_str().unwrap().strip_prefix("./assets/").unwrap().to_string(), name)
+    let mut list: Vec<(String, String)> = std::fs::read_dir(output.0.join("assets")).into_iter().flatten().flatten().map(|x| {
+        let path = x.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        (path.to_string_lossy().to_string(), name)
     }).collect();
 
     // Sort the list alphabetically
@@ -127,6 +315,16 @@ fn setup(
             }
     ));
     });
+    commands.spawn((
+        TextBundle::from_section("", TextStyle { font_size: 16., ..style.clone() })
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                ..Default::default()
+            }),
+        AssetInfo,
+    ));
 
     spawn_camera(commands, window);
     
@@ -197,6 +395,10 @@ fn scroll(
             continue;
         }
         time.time = Instant::now();
+        // nothing imported yet
+        if data.items.is_empty() {
+            continue;
+        }
         if keys.pressed(KeyCode::Up) {
             if data.selected > 0 {
                 data.selected -= 1;
@@ -206,19 +408,19 @@ fn scroll(
         } else if keys.pressed(KeyCode::Down) {
             if data.selected < max - 1 && data.selected < data.items.len() - 1 {
                 data.selected += 1;
-            } else if data.offset < data.items.len() - max {
+            } else if data.offset < data.items.len().saturating_sub(max) {
                 data.offset += 1;
             }
         } else if keys.pressed(KeyCode::PageDown) {
-            if data.selected < data.items.len() - max {
+            if data.selected < data.items.len().saturating_sub(max) {
                 data.selected += max;
             } else {
                 data.selected = data.items.len() - 1;
             }
-            if data.offset < data.items.len() - max {
+            if data.offset < data.items.len().saturating_sub(max) {
                 data.offset += max;
             } else {
-                data.offset = data.items.len() - max;
+                data.offset = data.items.len().saturating_sub(max);
             }
         } else if keys.pressed(KeyCode::PageUp) {
             if data.selected > max {
@@ -251,63 +453,141 @@ fn scroll(
     }
 }
 
+/// Where the importer wrote the materials, as an absolute path.
+#[derive(Resource)]
+struct OutputDirectory(PathBuf);
+
+/// The material folders, by the `.glb` primitives and glTF material names their manifests say they're used by.
+#[derive(Resource, Default)]
+struct MaterialFolders {
+    by_primitive: BTreeMap<MeshPrimitive, Vec<String>>,
+    by_name: HashMap<String, Vec<String>>,
+    /// Source path of the mesh each folder's material was matched to.
+    meshes: HashMap<String, String>,
+}
+
+impl MaterialFolders {
+    fn read<P: AsRef<Path>>(assets: P) -> MaterialFolders {
+        let mut folders = MaterialFolders::default();
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(assets).into_iter().flatten().flatten().map(|entry| entry.path()).collect();
+        paths.sort();
+        for path in paths {
+            let Ok(manifest) = MaterialManifest::read(&path) else {
+                continue;
+            };
+            let folder = path.to_string_lossy().to_string();
+            for primitive in manifest.used_by.iter().cloned() {
+                folders.by_primitive.entry(primitive).or_default().push(folder.clone());
+            }
+            if let Some(source) = manifest.mesh.as_ref().and_then(|mesh| manifest.source_of(mesh)) {
+                folders.meshes.insert(folder.clone(), source.clone());
+            }
+            folders.by_name.entry(manifest.name).or_default().push(folder);
+        }
+        folders
+    }
+    /// Folder of the material drawing `primitive` of the `.glb` at `source`. Materials sharing a name
+    /// are told apart by the mesh a prefab or the importer matched them to.
+    fn find(&self, source: &str, primitive: &GlbPrimitive) -> Option<&String> {
+        let key = MeshPrimitive { mesh: source.to_string(), mesh_index: primitive.mesh, primitive: primitive.primitive };
+        let folders = self.by_primitive.get(&key).or_else(|| self.by_name.get(primitive.material.as_ref()?))?;
+        folders.iter().find(|folder| self.meshes.get(*folder).map(String::as_str) == Some(source)).or(folders.first())
+    }
+}
+
+/// The GUID index written by the importer.
+#[derive(Resource)]
+struct Guids(GuidIndex);
+
+/// Text showing where the selected material came from.
+#[derive(Component)]
+struct AssetInfo;
+
 #[derive(Component)]
 struct AssetData {
     mesh: String,
-    albedo: String,
-    normal: String,
-    orm: String,
-    material_id: Handle<StandardMaterial>,
 } 
 
+/// The material picked in the list, by its folder.
+#[derive(Resource, Default)]
+struct SelectedMaterial(Option<(String, Handle<StandardMaterial>)>);
+
+/// Material folders whose normal maps are shown with Y flipped, toggled with N.
+#[derive(Resource, Default)]
+struct FlippedNormals(HashSet<String>);
+
+/// Textures waiting to load before their import settings are applied.
+#[derive(Resource, Default)]
+struct PendingTextureSettings(Vec<(Handle<Image>, TextureImportSettings)>);
+
+/// Tiling of the base color slot of a primitive's material, applied once the source mesh has loaded.
+#[derive(Component)]
+struct UvTiling {
+    source: Handle<Mesh>,
+    uv_transform: UvTransform,
+}
+
+#[allow(clippy::too_many_arguments)]
 fn change_asset(
-    current : Query<&mut VisibleItems>,
-    mut asset: Query<(&mut AssetData, &mut Handle<Mesh>)>,
+    mut commands: Commands,
+    mut current : Query<&mut VisibleItems>,
+    mut asset: Query<(Entity, &mut AssetData)>,
+    mut info: Query<&mut Text, With<AssetInfo>>,
+    guids: Res<Guids>,
+    folders: Res<MaterialFolders>,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>
-
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut pending: ResMut<PendingTextureSettings>,
+    mut selected_material: ResMut<SelectedMaterial>,
+    flipped: Res<FlippedNormals>,
+    render_device: Res<RenderDevice>,
 ) {
-    let current = current.get_single().unwrap();
-    if current.last_selected.is_some() && current.selected == current.last_selected.unwrap() {
+    let mut current = current.get_single_mut().unwrap();
+    let index = current.selected + current.offset;
+    if current.last_selected == Some(index) {
         return;
     }
-    let current = current.items[current.selected+current.offset].clone();
-    let (mut asset, mut mesh) = asset.get_single_mut().unwrap();
+    current.last_selected = Some(index);
+    let Some(current) = current.items.get(index).cloned() else {
+        return;
+    };
+    let (root, mut asset) = asset.get_single_mut().unwrap();
     let path = current.0;
-    let mut exists = (false,false, false, false);
-
-    for file in std::fs::read_dir(format!("./assets/{}",path)).unwrap() {
-        let path = file.unwrap().path();
-        let changed_path = path.to_str().unwrap().strip_prefix("./assets/").unwrap();
-        let name = path.file_name().unwrap().to_str().unwrap().to_string();
-        //println!("{}", changed_path);
-        if name.ends_with("glb") {
-            let asset_handle: Handle<Mesh> = asset_server.load(format!("{}#Mesh0/Primitive0",changed_path));
-            asset.mesh = changed_path.to_string();
-            *mesh = asset_handle;
-            exists.0 = true;
-        } else if name.ends_with("BaseColor.png") {
-            let asset_handle: Handle<Image> = asset_server.load(changed_path);
-            asset.albedo = changed_path.to_string();
-            materials.get_mut(&asset.material_id).unwrap().base_color_texture = Some(asset_handle);
-            exists.1 = true;
-        } else if name.ends_with("Normal.png") {
-            let asset_handle: Handle<Image> = asset_server.load(changed_path);
-            asset.normal = changed_path.to_string();
-            materials.get_mut(&asset.material_id).unwrap().normal_map_texture = Some(asset_handle);
-            exists.2 = true;
-        } else if name.ends_with("ORM.png") {
-            let asset_handle: Handle<Image> = asset_server.load(changed_path);
-            asset.orm = changed_path.to_string();
-            materials.get_mut(&asset.material_id).unwrap().metallic_roughness_texture = Some(asset_handle.clone());
-            materials.get_mut(&asset.material_id).unwrap().occlusion_texture = Some(asset_handle);
-            exists.3 = true;
-        }
-
+    let manifest = MaterialManifest::read(&path).unwrap_or_default();
+    if let Ok(mut info) = info.get_single_mut() {
+        let shader_path = manifest.shader_guid.as_deref().and_then(|guid| guids.0.path(guid));
+        let shader = match (&manifest.shader_name, shader_path) {
+            (Some(name), Some(path)) => format!("{} ({})", name, path),
+            (Some(name), None) => name.clone(),
+            (None, Some(path)) => path.to_string(),
+            (None, None) => "unknown".to_string(),
+        };
+        let mesh = match (&manifest.mesh, manifest.mesh_match) {
+            (Some(mesh), Some(MeshMatch::Prefab)) => format!("{} (from prefab)", mesh),
+            (Some(mesh), Some(MeshMatch::Gltf)) => format!("{} (from glTF material)", mesh),
+            (Some(mesh), Some(MeshMatch::Name)) => format!("{} (from name)", mesh),
+            (Some(mesh), None) => mesh.clone(),
+            (None, _) => "none".to_string(),
+        };
+        info.sections[0].value = format!(
+            "{}\nsource: {}\nguid: {}\nshader: {}\nprofile: {}\nmesh: {}",
+            manifest.name,
+            manifest.source,
+            manifest.guid.as_deref().unwrap_or("unknown"),
+            shader,
+            manifest.shader_profile,
+            mesh,
+        );
     }
-    if !exists.0 {
-        *mesh = meshes.add(Mesh::from(shape::Torus {
+    commands.entity(root).despawn_descendants();
+    // the texture cache is block compressed, GPUs without BC formats load the textures it came from
+    let use_cache = CompressedImageFormats::from_features(render_device.features()).contains(CompressedImageFormats::BC);
+    let selected = load_material(&path, &manifest, &asset_server, &mut materials, &mut pending, flipped.0.contains(&path), use_cache);
+    selected_material.0 = Some((path.clone(), selected.0.clone()));
+
+    let Some(mesh_file) = &manifest.mesh else {
+        let torus = meshes.add(Mesh::from(shape::Torus {
             radius: 0.1,
             ring_radius: 0.03,
             subdivisions_segments: 100,
@@ -315,17 +595,210 @@ fn change_asset(
         
         }));
         asset.mesh = "mesh/Torus".to_string();
+        commands.entity(root).with_children(|parent| {
+            parent.spawn(PbrBundle { mesh: torus, material: selected.0, ..Default::default() });
+        });
+        asset_server.free_unused_assets();
+        return;
+    };
+    asset.mesh = manifest.file_path(&path, mesh_file);
+
+    // a glb that can't be read is shown as its first primitive
+    let mut primitives = glb::read_primitives(&asset.mesh).unwrap_or_default();
+    if primitives.is_empty() {
+        primitives.push(GlbPrimitive { mesh: 0, primitive: 0, material: None });
     }
-    if !exists.1 {
-        materials.get_mut(&asset.material_id).unwrap().base_color_texture = Some(asset_server.load("textures/The_Missing_textures.png"));
-    }
-    if !exists.2 {
-        materials.get_mut(&asset.material_id).unwrap().normal_map_texture = None;
+    // the primitives are placed by the nodes of the glb's scene, meshes no node draws are shown at the origin
+    let mut instances = glb::read_instances(&asset.mesh).unwrap_or_default();
+    for mesh in primitives.iter().map(|primitive| primitive.mesh).collect::<HashSet<_>>() {
+        if !instances.iter().any(|instance| instance.mesh == mesh) {
+            instances.push(GlbInstance { mesh, transform: Mat4::IDENTITY });
+        }
     }
-    if !exists.3 {
-        materials.get_mut(&asset.material_id).unwrap().metallic_roughness_texture = None;
-        materials.get_mut(&asset.material_id).unwrap().occlusion_texture = None;
+    // when the glb names this material, primitives with materials that weren't imported get the default one,
+    // otherwise the glb's material names mean nothing here and they all show this material
+    let named = primitives.iter().any(|primitive| primitive.material.as_ref() == Some(&manifest.name));
+    let source = manifest.source_of(mesh_file).cloned().unwrap_or_else(|| asset.mesh.clone());
+    let mut resolved: HashMap<String, Option<LoadedMaterial>> = HashMap::new();
+    resolved.insert(path.clone(), Some(selected.clone()));
+    let mut default_material = None;
+    let mut parts = Vec::new();
+    for primitive in primitives {
+        let material = match primitive.material.as_ref() {
+            Some(name) if name == &manifest.name => Some(selected.clone()),
+            Some(_) => folders.find(&source, &primitive).and_then(|folder| {
+                resolved.entry(folder.clone()).or_insert_with(|| {
+                    let manifest = MaterialManifest::read(folder).ok()?;
+                    let flip_normal_map_y = flipped.0.contains(folder);
+                    Some(load_material(folder, &manifest, &asset_server, &mut materials, &mut pending, flip_normal_map_y, use_cache))
+                }).clone()
+            }),
+            None => None,
+        };
+        let (material, uv_transform) = match material {
+            Some(material) => material,
+            None if named => (default_material.get_or_insert_with(|| materials.add(StandardMaterial::default())).clone(), None),
+            None => selected.clone(),
+        };
+        let mesh = asset_server.load(format!("{}#Mesh{}/Primitive{}", asset.mesh, primitive.mesh, primitive.primitive));
+        parts.push((primitive.mesh, mesh, material, uv_transform));
     }
+    commands.entity(root).with_children(|parent| {
+        for instance in instances {
+            for (_, mesh, material, uv_transform) in parts.iter().filter(|part| part.0 == instance.mesh) {
+                let transform = Transform::from_matrix(instance.transform);
+                let mut entity = parent.spawn(PbrBundle { mesh: mesh.clone(), material: material.clone(), transform, ..Default::default() });
+                if let Some(uv_transform) = *uv_transform {
+                    entity.insert(UvTiling { source: mesh.clone(), uv_transform });
+                }
+            }
+        }
+    });
     asset_server.free_unused_assets();
 
-}
\ No newline at end of file
+}
+
+/// A material made from a manifest, and the tiling of its base color.
+type LoadedMaterial = (Handle<StandardMaterial>, Option<UvTransform>);
+
+/// Create the `StandardMaterial` of the imported material in `path`, and the tiling its mesh needs.
+fn load_material(
+    path: &str,
+    manifest: &MaterialManifest,
+    asset_server: &AssetServer,
+    materials: &mut Assets<StandardMaterial>,
+    pending: &mut PendingTextureSettings,
+    flip_normal_map_y: bool,
+    use_cache: bool,
+) -> LoadedMaterial {
+    let mut load = |texture: &Option<TextureRef>| -> Option<Handle<Image>> {
+        let texture = texture.as_ref()?;
+        let file = if use_cache { manifest.texture_path(path, &texture.file) } else { manifest.file_path(path, &texture.file) };
+        let image: Handle<Image> = asset_server.load(file);
+        pending.0.push((image.clone(), manifest.texture_settings_of(&texture.file)));
+        Some(image)
+    };
+    let mut material = StandardMaterial {
+        base_color_texture: load(&manifest.textures.base_color)
+            .or_else(|| Some(asset_server.load("textures/The_Missing_textures.png"))),
+        normal_map_texture: load(&manifest.textures.normal),
+        metallic_roughness_texture: load(&manifest.textures.metallic_roughness),
+        occlusion_texture: load(&manifest.textures.occlusion),
+        emissive_texture: load(&manifest.textures.emissive),
+        flip_normal_map_y,
+        ..Default::default()
+    };
+    apply_properties(&mut material, &manifest.properties);
+
+    // StandardMaterial has a single UV set, so the base color tiling is used for every texture
+    let uv_transform = manifest.textures.base_color.as_ref()
+        .and_then(|texture| manifest.uv_transforms.get(&texture.slot).copied())
+        .filter(|uv_transform| !uv_transform.is_identity());
+    (materials.add(material), uv_transform)
+}
+
+fn apply_properties(material: &mut StandardMaterial, properties: &MaterialProperties) {
+    let [r, g, b, a] = properties.base_color;
+    material.base_color = Color::rgba(r, g, b, a);
+    let [r, g, b, a] = properties.emissive;
+    material.emissive = Color::rgba_linear(r, g, b, a);
+    material.metallic = properties.metallic;
+    material.perceptual_roughness = properties.perceptual_roughness;
+    material.alpha_mode = match properties.alpha_mode {
+        manifest::AlphaMode::Opaque => AlphaMode::Opaque,
+        manifest::AlphaMode::Mask(cutoff) => AlphaMode::Mask(cutoff),
+        manifest::AlphaMode::Blend => AlphaMode::Blend,
+        manifest::AlphaMode::Premultiplied => AlphaMode::Premultiplied,
+    };
+    material.double_sided = properties.double_sided;
+    material.cull_mode = if properties.double_sided { None } else { Some(Face::Back) };
+}
+
+/// Flip the Y of the selected material's normal map, for normal maps authored with Y down.
+fn toggle_normal_flip(
+    keys: Res<Input<KeyCode>>,
+    selected: Res<SelectedMaterial>,
+    mut flipped: ResMut<FlippedNormals>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !keys.just_pressed(KeyCode::N) {
+        return;
+    }
+    let Some((path, handle)) = &selected.0 else {
+        return;
+    };
+    let flip = !flipped.0.remove(path);
+    if flip {
+        flipped.0.insert(path.clone());
+    }
+    if let Some(material) = materials.get_mut(handle) {
+        material.flip_normal_map_y = flip;
+    }
+}
+
+/// Set the color space and sampler of loaded textures from their import settings.
+fn apply_texture_settings(
+    mut pending: ResMut<PendingTextureSettings>,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+) {
+    pending.0.retain(|(handle, settings)| {
+        if asset_server.get_load_state(handle) == LoadState::Failed {
+            return false;
+        }
+        let Some(image) = images.get_mut(handle) else {
+            return true;
+        };
+        let format = &mut image.texture_descriptor.format;
+        *format = if settings.srgb { format.add_srgb_suffix() } else { format.remove_srgb_suffix() };
+        image.sampler_descriptor = ImageSampler::Descriptor(sampler_descriptor(settings));
+        false
+    });
+}
+
+fn sampler_descriptor(settings: &TextureImportSettings) -> SamplerDescriptor<'static> {
+    let address_mode = |wrap| match wrap {
+        WrapMode::Repeat => AddressMode::Repeat,
+        WrapMode::Clamp => AddressMode::ClampToEdge,
+        // wgpu has no mirror once, which only differs outside of -1..2
+        WrapMode::Mirror | WrapMode::MirrorOnce => AddressMode::MirrorRepeat,
+    };
+    // like Unity, point filtering turns anisotropy off, wgpu takes powers of two up to 16 with linear filters only
+    let (filter, mipmap_filter, anisotropy_clamp) = match (settings.filter, settings.aniso) {
+        (texture_settings::FilterMode::Point, _) => (FilterMode::Nearest, FilterMode::Nearest, 1),
+        (_, aniso @ 2..) => (FilterMode::Linear, FilterMode::Linear, 1 << aniso.min(16).ilog2()),
+        (texture_settings::FilterMode::Bilinear, _) => (FilterMode::Linear, FilterMode::Nearest, 1),
+        (texture_settings::FilterMode::Trilinear, _) => (FilterMode::Linear, FilterMode::Linear, 1),
+    };
+    SamplerDescriptor {
+        address_mode_u: address_mode(settings.wrap_u),
+        address_mode_v: address_mode(settings.wrap_v),
+        address_mode_w: AddressMode::Repeat,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter,
+        anisotropy_clamp,
+        ..Default::default()
+    }
+}
+
+/// Bake the pending tiling into a copy of the mesh, since StandardMaterial has no UV transform.
+fn apply_uv_transform(
+    mut commands: Commands,
+    mut primitives: Query<(Entity, &UvTiling, &mut Handle<Mesh>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (entity, tiling, mut mesh) in primitives.iter_mut() {
+        let Some(source_mesh) = meshes.get(&tiling.source) else {
+            continue;
+        };
+        let mut tiled = source_mesh.clone();
+        if let Some(VertexAttributeValues::Float32x2(uvs)) = tiled.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
+            for uv in uvs.iter_mut() {
+                *uv = tiling.uv_transform.apply(*uv);
+            }
+        }
+        *mesh = meshes.add(tiled);
+        commands.entity(entity).remove::<UvTiling>();
+    }
+}
@@ -0,0 +1,349 @@
+//! The `material.toml` written next to every imported material, and read back by the viewer.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use crate::texture_settings::TextureImportSettings;
+use crate::unity_yaml::UnityMaterial;
+
+pub const MANIFEST_FILE_NAME: &str = "material.toml";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct MaterialManifest {
+    /// `m_Name` of the material.
+    #[serde(default)]
+    pub name: String,
+    /// GUID of the material, from its `.meta` file.
+    #[serde(default)]
+    pub guid: Option<String>,
+    /// Path of the source `.mat` file.
+    #[serde(default)]
+    pub source: String,
+    /// GUID of the `m_Shader` reference.
+    #[serde(default)]
+    pub shader_guid: Option<String>,
+    /// Name of the shader, from its `.shader` or `.shadergraph` file or the built-in shaders.
+    #[serde(default)]
+    pub shader_name: Option<String>,
+    /// The `.glb` in the material folder rendered with this material.
+    #[serde(default)]
+    pub mesh: Option<String>,
+    /// How `mesh` was found.
+    #[serde(default)]
+    pub mesh_match: Option<MeshMatch>,
+    /// Every primitive of the export's `.glb` files drawn with this material.
+    #[serde(default)]
+    pub used_by: Vec<MeshPrimitive>,
+    /// Every file copied into the material folder, mapped to the path it was copied from.
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+    /// The files were left where they are instead of being placed in the material folder,
+    /// only the ones the importer generated are there.
+    #[serde(default)]
+    pub in_place: bool,
+    /// Textures converted into a format the viewer loads, written into the material folder,
+    /// mapped to the file of `files` they were converted from.
+    #[serde(default)]
+    pub converted: BTreeMap<String, String>,
+    /// `TextureImporter` settings of the textures, keyed by the file the texture was placed as.
+    #[serde(default)]
+    pub texture_settings: BTreeMap<String, TextureImportSettings>,
+    /// KTX2 copies of the textures in the material folder, see `texture_cache`, keyed by texture file.
+    #[serde(default)]
+    pub cached: BTreeMap<String, String>,
+    /// Name of the shader profile that assigned the textures.
+    #[serde(default)]
+    pub shader_profile: String,
+    #[serde(default)]
+    pub textures: TextureSlots,
+    /// Texture slots whose GUID is not in the export, slot name to GUID.
+    #[serde(default)]
+    pub unresolved: BTreeMap<String, String>,
+    pub properties: MaterialProperties,
+    /// Every `m_Floats` (and `m_Ints`) entry of the source material.
+    #[serde(default)]
+    pub floats: BTreeMap<String, f32>,
+    /// Every `m_Colors` entry of the source material.
+    #[serde(default)]
+    pub colors: BTreeMap<String, [f32; 4]>,
+    /// `m_Scale` / `m_Offset` of every texture slot that has a texture, keyed by slot name.
+    #[serde(default)]
+    pub uv_transforms: BTreeMap<String, UvTransform>,
+}
+
+impl MaterialManifest {
+    pub fn from_unity(material: &UnityMaterial) -> MaterialManifest {
+        MaterialManifest {
+            name: material.name.clone(),
+            guid: None,
+            source: String::new(),
+            shader_guid: material.shader.guid.clone(),
+            shader_name: None,
+            mesh: None,
+            mesh_match: None,
+            used_by: Vec::new(),
+            files: BTreeMap::new(),
+            in_place: false,
+            converted: BTreeMap::new(),
+            texture_settings: BTreeMap::new(),
+            cached: BTreeMap::new(),
+            shader_profile: String::new(),
+            textures: TextureSlots::default(),
+            unresolved: BTreeMap::new(),
+            properties: MaterialProperties::from_unity(material),
+            floats: material.floats.iter().cloned().collect(),
+            colors: material.colors.iter().cloned().collect(),
+            uv_transforms: material
+                .tex_envs
+                .iter()
+                .filter(|(_, tex_env)| !tex_env.texture.is_null())
+                .map(|(name, tex_env)| (name.clone(), UvTransform { scale: tex_env.scale, offset: tex_env.offset }))
+                .collect(),
+        }
+    }
+    /// Path of one of the material's files, `folder` being the material folder.
+    pub fn file_path(&self, folder: &str, file: &str) -> String {
+        match self.files.get(file) {
+            // Bevy takes absolute paths as they are, and joins relative ones to its asset folder
+            Some(source) if self.in_place => std::fs::canonicalize(source)
+                .map_or_else(|_| source.clone(), |source| source.to_string_lossy().to_string()),
+            _ => format!("{}/{}", folder, file),
+        }
+    }
+    /// Path the viewer loads a texture from, its cached copy when there is one.
+    pub fn texture_path(&self, folder: &str, file: &str) -> String {
+        match self.cached.get(file).map(|cached| format!("{}/{}", folder, cached)) {
+            Some(cached) if Path::new(&cached).exists() => cached,
+            _ => self.file_path(folder, file),
+        }
+    }
+    /// Source path of one of the material's files, the source of the original for converted textures.
+    pub fn source_of(&self, file: &str) -> Option<&String> {
+        let file = self.converted.get(file).map_or(file, String::as_str);
+        self.files.get(file)
+    }
+    /// Import settings of one of the material's textures, the ones of the original for converted textures.
+    pub fn texture_settings_of(&self, file: &str) -> TextureImportSettings {
+        let file = self.converted.get(file).map_or(file, String::as_str);
+        self.texture_settings.get(file).copied().unwrap_or_default()
+    }
+    pub fn read<P: AsRef<Path>>(folder: P) -> Result<MaterialManifest, Box<dyn Error>> {
+        let text = std::fs::read_to_string(folder.as_ref().join(MANIFEST_FILE_NAME))?;
+        Ok(toml::from_str(&text)?)
+    }
+    pub fn write<P: AsRef<Path>>(&self, folder: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(folder.as_ref().join(MANIFEST_FILE_NAME), toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// How the mesh of a material was found.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshMatch {
+    /// A prefab renders the mesh with the material.
+    Prefab,
+    /// A primitive of the mesh uses a glTF material of the same name.
+    Gltf,
+    /// The mesh path contains the second `_`-separated word of the material name.
+    Name,
+}
+
+/// A primitive of a `.glb`, by the path of the file and the indices in Bevy's `MeshN/PrimitiveM` labels.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MeshPrimitive {
+    pub mesh: String,
+    pub mesh_index: usize,
+    pub primitive: usize,
+}
+
+/// A texture copied into the material folder, and the `m_TexEnvs` slot it came from.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct TextureRef {
+    pub slot: String,
+    pub file: String,
+}
+
+/// Textures for each `StandardMaterial` slot.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct TextureSlots {
+    pub base_color: Option<TextureRef>,
+    pub normal: Option<TextureRef>,
+    pub metallic_roughness: Option<TextureRef>,
+    pub occlusion: Option<TextureRef>,
+    pub emissive: Option<TextureRef>,
+}
+
+impl TextureSlots {
+    pub fn slots_mut(&mut self) -> [&mut Option<TextureRef>; 5] {
+        [
+            &mut self.base_color,
+            &mut self.normal,
+            &mut self.metallic_roughness,
+            &mut self.occlusion,
+            &mut self.emissive,
+        ]
+    }
+}
+
+/// Unity texture tiling, `uv * scale + offset` in Unity's bottom-left UV space.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct UvTransform {
+    pub scale: [f32; 2],
+    pub offset: [f32; 2],
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        UvTransform {
+            scale: [1.0, 1.0],
+            offset: [0.0, 0.0],
+        }
+    }
+}
+
+impl UvTransform {
+    pub fn is_identity(&self) -> bool {
+        *self == UvTransform::default()
+    }
+    /// Transform a glTF UV. glTF has its origin in the top left, so V is flipped
+    /// into Unity's space and back around the transform.
+    pub fn apply(&self, [u, v]: [f32; 2]) -> [f32; 2] {
+        [
+            u * self.scale[0] + self.offset[0],
+            1.0 - ((1.0 - v) * self.scale[1] + self.offset[1]),
+        ]
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    /// Alpha tested against the cutoff.
+    Mask(f32),
+    Blend,
+    Premultiplied,
+}
+
+/// Scalar and color values mapped onto the fields of Bevy's `StandardMaterial`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct MaterialProperties {
+    /// sRGB base color, multiplied with the base color texture.
+    pub base_color: [f32; 4],
+    /// Linear emissive color.
+    pub emissive: [f32; 4],
+    pub metallic: f32,
+    pub perceptual_roughness: f32,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+}
+
+impl Default for MaterialProperties {
+    fn default() -> Self {
+        MaterialProperties {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            emissive: [0.0, 0.0, 0.0, 1.0],
+            metallic: 0.0,
+            perceptual_roughness: 0.5,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+}
+
+impl MaterialProperties {
+    /// Read the properties used by the HDRP Lit, URP Lit and Built-in Standard shaders.
+    /// Custom shaders usually reuse the same names, anything else keeps Bevy's defaults.
+    pub fn from_unity(material: &UnityMaterial) -> MaterialProperties {
+        let mut properties = MaterialProperties::default();
+        let flag = |name: &str| material.float(name).map(|value| value != 0.0);
+
+        if let Some(color) = material.color("_BaseColor").or_else(|| material.color("_Color")) {
+            properties.base_color = color;
+        }
+
+        // HDRP always uses _EmissiveColor, Built-in and URP only emit with the _EMISSION keyword
+        if let Some(color) = material.color("_EmissiveColor") {
+            properties.emissive = color;
+        } else if material.has_keyword("_EMISSION") {
+            if let Some(color) = material.color("_EmissionColor") {
+                properties.emissive = color;
+            }
+        }
+
+        if let Some(metallic) = material.float("_Metallic") {
+            properties.metallic = metallic.clamp(0.0, 1.0);
+        }
+        if let Some(smoothness) = material.float("_Smoothness").or_else(|| material.float("_Glossiness")) {
+            properties.perceptual_roughness = (1.0 - smoothness).clamp(0.0, 1.0);
+        }
+
+        let cutoff = |name: &str| AlphaMode::Mask(material.float(name).unwrap_or(0.5));
+        properties.alpha_mode = if let Some(surface) = material.float("_SurfaceType") {
+            // HDRP Lit
+            match (flag("_AlphaCutoffEnable").unwrap_or(false), surface != 0.0) {
+                (true, _) => cutoff("_AlphaCutoff"),
+                (false, true) => AlphaMode::Blend,
+                (false, false) => AlphaMode::Opaque,
+            }
+        } else if let Some(surface) = material.float("_Surface") {
+            // URP Lit
+            match (flag("_AlphaClip").unwrap_or(false), surface != 0.0) {
+                (true, _) => cutoff("_Cutoff"),
+                (false, true) => AlphaMode::Blend,
+                (false, false) => AlphaMode::Opaque,
+            }
+        } else {
+            // Built-in Standard: Opaque, Cutout, Fade, Transparent
+            match material.float("_Mode").unwrap_or(0.0) as i32 {
+                1 => cutoff("_Cutoff"),
+                2 => AlphaMode::Blend,
+                3 => AlphaMode::Premultiplied,
+                _ => AlphaMode::Opaque,
+            }
+        };
+
+        // HDRP has an explicit toggle, URP and Built-in expose the cull mode, where 0 is Off
+        properties.double_sided = flag("_DoubleSidedEnable")
+            .or_else(|| material.float("_Cull").map(|cull| cull == 0.0))
+            .unwrap_or(false);
+
+        properties
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiled() -> MaterialManifest {
+        let text = std::fs::read_to_string("assets/materials/M_Apple_01_Tiled.mat").unwrap();
+        MaterialManifest::from_unity(&UnityMaterial::parse(&text).unwrap())
+    }
+
+    #[test]
+    fn from_unity_reads_uv_transforms() {
+        let manifest = tiled();
+        let tiled = UvTransform {
+            scale: [4.0, 2.0],
+            offset: [0.25, 0.5],
+        };
+        // every slot with a texture, the empty lightmap slot is left out
+        assert_eq!(manifest.uv_transforms.keys().collect::<Vec<_>>(), ["BaseColor", "Normal", "ORM"]);
+        assert!(manifest.uv_transforms.values().all(|transform| *transform == tiled));
+        assert!(!tiled.is_identity());
+        assert!(UvTransform::default().is_identity());
+    }
+
+    #[test]
+    fn uv_transform_applies_in_unity_space() {
+        let transform = tiled().uv_transforms["BaseColor"];
+        // glTF's top left corner is Unity's (0, 1)
+        assert_eq!(transform.apply([0.0, 0.0]), [0.25, -1.5]);
+        // glTF's bottom left corner is Unity's origin, moved by the offset only
+        assert_eq!(transform.apply([0.0, 1.0]), [0.25, 0.5]);
+        assert_eq!(transform.apply([1.0, 1.0]), [4.25, 0.5]);
+        assert_eq!(transform.apply([0.5, 0.5]), [2.25, -0.5]);
+        assert_eq!(UvTransform::default().apply([0.25, 0.75]), [0.25, 0.75]);
+    }
+}
@@ -1,475 +1,1179 @@
-use std::{path::{PathBuf, Path}, fs::File, io::{Read, self, Write}, process::exit};
-use blake3::{Hasher, Hash};
+use std::{collections::BTreeMap, path::{PathBuf, Path}, io};
 use arrayvec::ArrayString;
 use std::error::Error;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use signal_hook::consts::signal::*;
 use signal_hook::flag;
 use indicatif::{ProgressBar, ProgressStyle};
 use walkdir::WalkDir;
 
+use crate::file_hashes::{modified, FileHashes, FILE_HASHES_FILE_NAME};
+use crate::glb::read_primitives;
+use crate::guid_index::{GuidEntry, GuidIndex, GUID_INDEX_FILE_NAME};
+use crate::import_log::{
+    Data, Header, ImportLog, Journal, LinkJournal, Scan, ScannedFile, JOURNAL_FILE_NAME, LINK_JOURNAL_FILE_NAME, LOG_FILE_NAME,
+    OUTPUT_VERSION, SCHEMA_VERSION, sync_folder,
+};
+use crate::import_plan::{ImportPlan, PlannedMaterial};
+use crate::import_report::{ImportReport, UnresolvedTexture};
+use crate::manifest::{MaterialManifest, MeshMatch, MeshPrimitive, TextureRef, MANIFEST_FILE_NAME};
+use crate::prefab::{parse_prefab, MeshBinding};
+use crate::shader_name::shader_name;
+use crate::shader_profile::{Packing, ShaderProfiles};
+use crate::texture_bake::{bake_normal_map, bake_orm};
+use crate::texture_cache::{cache_texture, is_cacheable, CACHE_EXTENSION};
+use crate::texture_convert::{convert_texture, converted_extension};
+use crate::texture_settings::TextureImportSettings;
+use crate::unity_yaml::{FileRef, UnityMaterial};
+
 pub struct Materials {
     directory: String,
     output_directory: String,
-    guids: Vec<(String, String)>,
+    guids: GuidIndex,
     completed: bool,
     step: usize,
-    current_material: usize,
+    progress: Mutex<Progress>,
     materials: Vec<PathBuf>,
+    /// File names shared by several `.mat` files, their folders get the material's GUID appended.
+    duplicate_names: BTreeSet<String>,
     mesh_files: Vec<PathBuf>,
+    /// Meshes prefabs render each material with, by path.
+    mesh_links: BTreeMap<String, Vec<String>>,
+    /// Primitives of the `.glb` files drawn with each glTF material, by material name.
+    used_by: BTreeMap<String, Vec<MeshPrimitive>>,
     total_materials: usize,
     hash: Option<ArrayString<64>>,
     is_terminating: Arc<AtomicBool>,
+    shader_profiles: ShaderProfiles,
+    threads: usize,
+    file_hashes: FileHashes,
+    /// Files that changed since the last import, when updating an earlier import.
+    changed_files: Option<BTreeSet<String>>,
+    /// Materials an unfinished import still had to do, done again whatever changed since.
+    unfinished: BTreeSet<PathBuf>,
+    /// Files that were skipped because they could not be read or parsed.
+    errors: Mutex<Vec<MatGenError>>,
+    /// Shader names by GUID and fileID, so each shader file is read once.
+    shader_names: Mutex<HashMap<(String, i64), Option<String>>>,
+    output_mode: OutputMode,
+    /// Start over instead of failing when the import log can't be read.
+    discard_log: bool,
+    /// Flip the green channel of normal maps, for exports with DirectX normal maps.
+    flip_normal_green: bool,
+    /// Write the KTX2 texture cache into the material folders.
+    texture_cache: bool,
 }
 
-impl Materials {
-    fn check(&mut self) -> Result<usize, Box<dyn Error>> {
-        let directory = self.output_directory.clone();
-        let toml = std::fs::File::open(format!("{}/log.toml", directory));
-        match toml {
-            Ok(_) => {
-                let mut toml = toml.unwrap();
-                let mut toml_string = String::new();
-                toml.read_to_string(&mut toml_string).unwrap();
-                let toml: Toml = toml::from_str(&toml_string).unwrap();
-                let hash = hash_from_hex_str(&toml.header.hash).unwrap();
-                // if version or hash don't match, restart
-                // if completed, skip
-                // if not completed, continue from toml.current
-                if toml.header.version == env!("CARGO_PKG_VERSION") {
-                    if hash == hash_directory(self.directory.clone()).unwrap() {     
-                        if !toml.header.completed {
-                            println!("Hashes match, but not completed, continuing");
-
-                            self.current_material = toml.current;
-                            self.materials = toml.data.materials;
-                            self.total_materials = toml.total;
-                            self.guids = toml.data.guids;
-                            self.hash = Some(hash.to_hex());
-                            self.step = toml.step;
-                            return Ok(toml.step);
+/// How the files of a material end up in its output folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    #[default]
+    Copy,
+    /// Hard link to the source, copied when the output is on another filesystem.
+    Hardlink,
+    /// Symbolic link to the source, copied when links can't be created.
+    Symlink,
+    /// Nothing is placed in the folder, the manifest points at the source files.
+    ManifestOnly,
+}
 
-                        }
-                        println!("Hashes match, completed, skipping");
-                        Err("Hashes match, completed, skipping")?;
-                    }
-                    if toml.header.completed {
-                        println!("Completed, skipping");
-                        Err("Completed, skipping")?;
-                    }
-                    println!("Hashes don't match, restarting");
-                    
-                    Ok(0)
-                } else {
-                    println!("Version mismatch, restarting");
-                    Ok(0)
-                }
-            
+impl OutputMode {
+    const ALL: [OutputMode; 4] = [OutputMode::Copy, OutputMode::Hardlink, OutputMode::Symlink, OutputMode::ManifestOnly];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OutputMode::Copy => "copy",
+            OutputMode::Hardlink => "hardlink",
+            OutputMode::Symlink => "symlink",
+            OutputMode::ManifestOnly => "manifest-only",
+        }
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+    fn from_str(mode: &str) -> Result<OutputMode, String> {
+        OutputMode::ALL
+            .into_iter()
+            .find(|output_mode| output_mode.as_str() == mode)
+            .ok_or_else(|| format!("unknown output mode {}, expected copy, hardlink, symlink or manifest-only", mode))
+    }
+}
+
+/// Outcome of `Materials::run`.
+#[derive(Debug)]
+pub struct ImportSummary {
+    pub status: ImportStatus,
+    /// The report of the last completed import, `None` when interrupted.
+    pub report: Option<ImportReport>,
+    /// Files that were skipped, the rest of the import went on without them.
+    pub errors: Vec<MatGenError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStatus {
+    Completed,
+    /// Nothing changed since the last completed import.
+    UpToDate,
+    /// Stopped by SIGINT or SIGTERM, the next run continues where this one stopped.
+    Interrupted,
+}
+
+/// Which materials are done, in the order of `Materials::materials`.
+/// Workers finish out of order, so everything past `current` is tracked separately.
+#[derive(Default)]
+struct Progress {
+    /// Every material before this index is done.
+    current: usize,
+    /// Materials after `current` that are already done.
+    done_ahead: BTreeSet<usize>,
+}
+
+impl Progress {
+    fn complete(&mut self, index: usize) {
+        self.done_ahead.insert(index);
+        while self.done_ahead.remove(&self.current) {
+            self.current += 1;
+        }
+    }
+    fn is_done(&self, index: usize) -> bool {
+        index < self.current || self.done_ahead.contains(&index)
+    }
+}
 
+impl Materials {
+    /// Hash the export, files that can't be read are reported and left out of the hashes.
+    fn scan_hashes(&mut self, previous: &FileHashes) {
+        let (file_hashes, unreadable) = FileHashes::scan(&self.directory, previous);
+        self.file_hashes = file_hashes;
+        let errors = self.errors.get_mut().unwrap();
+        for (path, source) in unreadable {
+            let error = MatGenError::Io { path, source };
+            if !is_recorded(errors, &error) {
+                eprintln!("Skipped {}", error);
+                errors.push(error);
             }
-            Err(_) => {
+        }
+    }
+    /// Compare the export with the last import, returns the step to start from,
+    /// or `None` when the last import completed and nothing changed since.
+    fn check(&mut self) -> Result<Option<usize>, MatGenError> {
+        let directory = self.output_directory.clone();
+        let log_path = Path::new(&directory).join(LOG_FILE_NAME);
+        let toml = match ImportLog::read(&log_path) {
+            Ok(Some(toml)) => toml,
+            Ok(None) => {
                 println!("No log file found, starting from scratch");
-                Ok(0)
+                return Ok(Some(0));
+            }
+            Err(e) if self.discard_log => {
+                println!("Discarding the import log, restarting: {}", e);
+                return Ok(Some(0));
             }
+            Err(e) => return Err(MatGenError::Log { path: log_path, message: e.to_string() }),
+        };
+        if let Some(schema) = toml.migrated_from {
+            println!("Import log migrated from schema {} to {}", schema, SCHEMA_VERSION);
         }
-
+        // if the output format, output mode, normal map flip, texture cache or shader profiles changed
+        // or there are no previous hashes, restart
+        // if nothing changed and completed, skip
+        // if nothing changed and not completed, continue from toml.current
+        // if files changed, only redo the materials that use them
+        if toml.header.output_version != OUTPUT_VERSION {
+            println!("Output format changed, restarting");
+            return Ok(Some(0));
+        }
+        if toml.header.output_mode != self.output_mode.as_str() {
+            println!("Output mode changed, restarting");
+            return Ok(Some(0));
+        }
+        if toml.header.flip_normal_green != self.flip_normal_green {
+            println!("Normal map green flip changed, restarting");
+            return Ok(Some(0));
+        }
+        if toml.header.texture_cache != self.texture_cache {
+            println!("Texture cache setting changed, restarting");
+            return Ok(Some(0));
+        }
+        if toml.header.shader_profiles != self.shader_profiles.digest() {
+            println!("Shader profiles changed, restarting");
+            return Ok(Some(0));
+        }
+        let Ok(previous) = FileHashes::load(format!("{}/{}", directory, FILE_HASHES_FILE_NAME)) else {
+            println!("No file hashes found, restarting");
+            return Ok(Some(0));
+        };
+        self.scan_hashes(&previous);
+        let changed = self.file_hashes.changed_since(&previous);
+        if changed.is_empty() && toml.header.completed {
+            println!("Hashes match, completed, skipping");
+            return Ok(None);
+        }
+        // materials finished after the log was written
+        let journal_path = Path::new(&directory).join(JOURNAL_FILE_NAME);
+        let journal = Journal::read(&journal_path).map_err(MatGenError::io(&journal_path))?;
+        let mut progress = Progress {
+            current: toml.current,
+            done_ahead: toml.done_ahead.into_iter().collect(),
+        };
+        let indices: HashMap<&Path, usize> =
+            toml.data.materials.iter().enumerate().map(|(i, path)| (path.as_path(), i)).collect();
+        for material in &journal {
+            if let Some(&i) = indices.get(material.as_path()) {
+                progress.complete(i);
+            }
+        }
+        if changed.is_empty() {
+            println!("Hashes match, but not completed, continuing");
+            if !journal.is_empty() {
+                println!("{} materials finished since the log was written", journal.len());
+            }
+            self.progress = Mutex::new(progress);
+            self.materials = toml.data.materials;
+            self.duplicate_names = duplicate_names(&self.materials);
+            self.mesh_files = toml.data.meshes;
+            self.mesh_links = toml.data.mesh_links;
+            self.used_by = toml.data.used_by;
+            self.total_materials = toml.total;
+            let Ok(guids) = GuidIndex::load(format!("{}/{}", directory, GUID_INDEX_FILE_NAME)) else {
+                println!("GUID index missing, restarting");
+                return Ok(Some(0));
+            };
+            self.guids = guids;
+            self.hash = Some(self.file_hashes.digest().to_hex());
+            self.step = toml.step;
+            return Ok(Some(toml.step));
+        }
+        println!("{} files changed, updating", changed.len());
+        if !toml.header.completed {
+            // the hashes were replaced when the unfinished import started, so what it still had to redo
+            // isn't among the changed files anymore
+            self.unfinished = toml.data.materials.into_iter()
+                .enumerate()
+                .filter(|(i, _)| !progress.is_done(*i))
+                .map(|(_, material)| material)
+                .collect();
+            println!("{} materials left over from the unfinished import", self.unfinished.len());
+        }
+        self.changed_files = Some(changed);
+        Ok(Some(0))
     }
     pub fn from_dir(input: &str, output: &str) -> Materials {
         Materials {
             directory: input.to_string(),
             output_directory: output.to_string(),
-            guids: Vec::new(),
+            guids: GuidIndex::new(),
             completed: false,
             step: 0,
-            current_material: 0,
+            progress: Mutex::new(Progress::default()),
             materials: Vec::new(),
+            duplicate_names: BTreeSet::new(),
             mesh_files: Vec::new(),
+            mesh_links: BTreeMap::new(),
+            used_by: BTreeMap::new(),
             hash: None,
             total_materials: 0,
             is_terminating: Arc::new(AtomicBool::new(false)),
+            shader_profiles: ShaderProfiles::builtin(),
+            threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            file_hashes: FileHashes::default(),
+            changed_files: None,
+            unfinished: BTreeSet::new(),
+            errors: Mutex::new(Vec::new()),
+            shader_names: Mutex::new(HashMap::new()),
+            output_mode: OutputMode::default(),
+            discard_log: false,
+            flip_normal_green: false,
+            texture_cache: false,
         }
     }
-    pub fn run(mut self) -> Result<(), Box<dyn Error>> {
-        flag::register(SIGINT, Arc::clone(&self.is_terminating))?;
-        flag::register(SIGTERM, Arc::clone(&self.is_terminating))?;
-        match self.check()? {
-            0 => {
-                self.linker()?;
-                self.parse_materials()?;
-                self.log_progress()?;
+    /// Number of materials processed at the same time, defaults to the number of CPUs.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+    pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+    }
+    /// Whether an import log that is corrupt or from a newer version is discarded, restarting the import.
+    pub fn set_discard_log(&mut self, discard_log: bool) {
+        self.discard_log = discard_log;
+    }
+    /// Whether the green channel of normal maps is flipped, Y down to the Y up Unity and Bevy use.
+    pub fn set_flip_normal_green(&mut self, flip_normal_green: bool) {
+        self.flip_normal_green = flip_normal_green;
+    }
+    /// Whether mipmapped KTX2 copies of the textures are written for the viewer.
+    pub fn set_texture_cache(&mut self, texture_cache: bool) {
+        self.texture_cache = texture_cache;
+    }
+    /// Use the shader profiles from a TOML file, in addition to the built-in ones.
+    pub fn load_shader_profiles<P: AsRef<Path>>(&mut self, path: P) -> Result<(), MatGenError> {
+        self.shader_profiles = ShaderProfiles::load(&path).map_err(MatGenError::parse(&path))?;
+        Ok(())
+    }
+    /// Import the export. Files that can't be read or parsed are skipped and listed in the summary,
+    /// only errors writing the output stop the import.
+    pub fn run(mut self) -> Result<ImportSummary, MatGenError> {
+        flag::register(SIGINT, Arc::clone(&self.is_terminating)).map_err(MatGenError::Signal)?;
+        flag::register(SIGTERM, Arc::clone(&self.is_terminating)).map_err(MatGenError::Signal)?;
+        let status = match self.check()? {
+            None => ImportStatus::UpToDate,
+            Some(0) => match self.linker(true)? {
+                ImportStatus::Interrupted => ImportStatus::Interrupted,
+                _ => {
+                    if self.changed_files.is_some() {
+                        for folder in self.removed_outputs() {
+                            println!("{}: removed", folder.display());
+                            std::fs::remove_dir_all(&folder).map_err(MatGenError::io(&folder))?;
+                        }
+                        self.skip_unchanged();
+                    }
+                    self.parse_materials()?
+                }
+            },
+            Some(1) => self.parse_materials()?,
+            Some(step) => return Err(MatGenError::InvalidStep(step)),
+        };
+        let report = match status {
+            ImportStatus::Completed => {
+                let report = self.report();
+                report.write(&self.output_directory).map_err(MatGenError::write(&self.output_directory))?;
+                Some(report)
             }
-            1 => {
-                self.parse_materials()?;
-                self.log_progress()?;
+            ImportStatus::UpToDate => ImportReport::read(&self.output_directory).ok(),
+            ImportStatus::Interrupted => None,
+        };
+        Ok(ImportSummary {
+            status,
+            report,
+            errors: self.errors.into_inner().unwrap(),
+        })
+    }
+    /// Work out what `run` would do, without writing or deleting anything.
+    pub fn plan(mut self) -> Result<ImportPlan, MatGenError> {
+        let mut plan = ImportPlan {
+            output_directory: self.output_directory.clone(),
+            output_mode: self.output_mode.as_str().to_string(),
+            ..Default::default()
+        };
+        match self.check()? {
+            None => {
+                plan.up_to_date = true;
+                return Ok(plan);
             }
-            _ => {
-                println!("Invalid step");
-                exit(1);
+            Some(0) => {
+                self.linker(false)?;
+                if self.changed_files.is_some() {
+                    plan.deletions.extend(self.removed_outputs().iter().map(|folder| folder.to_string_lossy().to_string()));
+                    self.skip_unchanged();
+                }
             }
-
+            Some(1) => {}
+            Some(step) => return Err(MatGenError::InvalidStep(step)),
         }
-        Ok(())
-    }
-    fn linker(&mut self) -> Result<(), Box<dyn Error>> {
-        println!("Linking materials");
-        let directory = self.directory.clone();
-
-        let mut folders_vec: Vec<PathBuf> = Vec::new();
-        // find all folders in directory, recursively
-        for entry in WalkDir::new(directory).into_iter().filter_entry(|e| e.file_type().is_dir()) {
-            match entry {
-                Ok(dir) => {
-                    folders_vec.push(dir.into_path());
+        let progress = std::mem::take(self.progress.get_mut().unwrap());
+        let resuming = progress.current > 0 || !progress.done_ahead.is_empty();
+        if !resuming && Path::new(&self.output_directory).exists() {
+            plan.deletions.push(self.output_directory.clone());
+        }
+        self.materials.sort_by_key(|path| path.clone());
+        for (i, material) in self.materials.iter().enumerate() {
+            if progress.is_done(i) {
+                plan.unchanged += 1;
+                continue;
+            }
+            match self.plan_material(material) {
+                Ok(MaterialPlan { folder, manifest, .. }) => {
+                    if resuming && folder.exists() {
+                        plan.deletions.push(folder.to_string_lossy().to_string());
+                    }
+                    plan.materials.push(PlannedMaterial {
+                        folder: folder.to_string_lossy().to_string(),
+                        manifest,
+                    });
                 }
-                Err(e) => println!("Error: {}", e),
+                Err(error) => self.errors.get_mut().unwrap().push(error),
             }
         }
-    
-        
-        let mut vec: Vec<(String, String)> =  Vec::new();
-        // only get files that end with .mat
+        plan.errors = self.errors.into_inner().unwrap().iter().map(|error| error.to_string()).collect();
+        Ok(plan)
+    }
+    /// Index the export and link materials to meshes, returns `Interrupted` when stopped by a signal.
+    /// With `persist`, what was read is journaled so an interrupted scan can continue.
+    fn linker(&mut self, persist: bool) -> Result<ImportStatus, MatGenError> {
+        println!("Linking materials");
+        let mut guids = GuidIndex::new();
         let mut material_files = Vec::new();
         let mut mesh_files = Vec::new();
+        let mut prefab_files = Vec::new();
+        let mut to_read = Vec::new();
+        let errors = self.errors.get_mut().unwrap();
 
-
-        // only get files that end with .meta
-        let meta_files_vec: Vec<Vec<(String, String)>> = folders_vec
-            .iter()
-            .map(|folder| {
-                folder
-                    .read_dir()
-                    .unwrap()
-                    .filter_map(|entry| {
-                        let entry = entry.unwrap();
-                        let path = entry.path();
-                        if path.is_file() && path.extension().unwrap() == "meta" {
-                            let file_path = path.to_str().unwrap();
-                            let file_name = file_path.strip_suffix(".meta").unwrap();
-                            Some((std::fs::read_to_string(entry.path()).unwrap(), file_name.to_string()))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
-            })
-            .collect();
-
-        for folder in folders_vec {
-            
-            let local_material_files = folder
-            .read_dir()?
-            .filter_map(|entry| {
-                let entry = entry.unwrap();
-                let path = entry.path();
-                if path.is_file() && path.extension().unwrap() == "mat" {
-                    Some(path)
-                } else {
-                    None
+        // collect the .meta, .prefab and .glb files to read, and the .mat files
+        for entry in WalkDir::new(&self.directory) {
+            if self.is_terminating.load(Ordering::Relaxed) {
+                eprintln!("Interrupted! Exiting gracefully...");
+                return Ok(ImportStatus::Interrupted);
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path().unwrap_or(Path::new(&self.directory)).to_path_buf();
+                    let error = MatGenError::Io { path, source: e.into() };
+                    if !is_recorded(errors, &error) {
+                        eprintln!("Skipped {}", error);
+                        errors.push(error);
+                    }
+                    continue;
                 }
-            })
-            .collect::<Vec<_>>();
-            material_files.extend(local_material_files);
-            let local_mesh_files = folder
-            .read_dir()?
-            .filter_map(|entry| {
-                let entry = entry.unwrap();
-                let path = entry.path();
-                if path.is_file() && path.extension().unwrap() == "glb" {
-                    Some(path)
-                } else {
-                    None
+            };
+            let path = entry.into_path();
+            if !path.is_file() {
+                continue;
+            }
+            match path.extension().and_then(OsStr::to_str) {
+                Some("meta") => to_read.push(path),
+                Some("mat") => material_files.push(path),
+                Some("glb") => {
+                    mesh_files.push(path.clone());
+                    to_read.push(path);
                 }
-            })
-            .collect::<Vec<_>>();
-            mesh_files.extend(local_mesh_files);
-
+                Some("prefab") => {
+                    prefab_files.push(path.clone());
+                    to_read.push(path);
+                }
+                _ => {}
+            }
+        }
 
+        // files read by an earlier or interrupted scan are reused as long as their size and modification time match
+        let journal_path = Path::new(&self.output_directory).join(LINK_JOURNAL_FILE_NAME);
+        let scanned = LinkJournal::read(&journal_path, &self.directory).map_err(MatGenError::io(&journal_path))?;
+        if !scanned.is_empty() {
+            println!("{} files read by an earlier scan", scanned.len());
+        }
+        let mut journal = match persist {
+            true => {
+                std::fs::create_dir_all(&self.output_directory).map_err(MatGenError::io(&self.output_directory))?;
+                Some(LinkJournal::create(&journal_path, &self.directory, &scanned).map_err(MatGenError::io(&journal_path))?)
+            }
+            false => None,
+        };
+        let mut scanned: HashMap<String, ScannedFile> = scanned.into_iter().map(|file| (file.path.clone(), file)).collect();
 
+        let bar = progress_bar(to_read.len());
+        let mut scans = Vec::new();
+        for path in to_read {
+            if self.is_terminating.load(Ordering::Relaxed) {
+                bar.abandon();
+                eprintln!("Interrupted! Exiting gracefully...");
+                return Ok(ImportStatus::Interrupted);
+            }
+            bar.inc(1);
+            let metadata = match path.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    let error = MatGenError::Io { path, source: e };
+                    if !is_recorded(errors, &error) {
+                        bar.println(format!("Skipped {}", error));
+                        errors.push(error);
+                    }
+                    continue;
+                }
+            };
+            let (size, mtime) = (metadata.len(), modified(&metadata));
+            let key = path.to_string_lossy().to_string();
+            match scanned.remove(&key) {
+                Some(file) if file.size == size && file.mtime == mtime => scans.push(file),
+                _ => match scan_file(&path) {
+                    Ok(scan) => {
+                        let file = ScannedFile { path: key, size, mtime, scan };
+                        if let Some(journal) = &mut journal {
+                            journal.append(&file).map_err(MatGenError::io(&journal_path))?;
+                        }
+                        scans.push(file);
+                    }
+                    Err(error) => {
+                        if !is_recorded(errors, &error) {
+                            bar.println(format!("Skipped {}", error));
+                            errors.push(error);
+                        }
+                    }
+                },
+            }
         }
-        // get the line that starts with "guid:", and push to the hashmap,
-        // the guid, and the file path, but with .meta suffix removed
-        for meta_files in meta_files_vec {
-            for (meta_file, path) in meta_files {
-                let guid = meta_file
-                    .lines()
-                    .find(|line| line.starts_with("guid:"))
-                    .unwrap()
-                    .strip_prefix("guid: ")
-                    .unwrap();
-                //println!("{} {}", guid, path);
-                vec.push((guid.to_string(), path.clone()));
+        bar.finish_and_clear();
+        // kept for the next update, without the files that are gone
+        if journal.is_some() {
+            LinkJournal::create(&journal_path, &self.directory, &scans).map_err(MatGenError::io(&journal_path))?;
+        }
+
+        // index the .meta files by guid with the file path, but with .meta suffix removed
+        let mut bindings = Vec::new();
+        let mut primitives = Vec::new();
+        for file in scans {
+            match file.scan {
+                Scan::Meta { guid, entry } => guids.insert(guid, entry),
+                Scan::Prefab(prefab_bindings) => bindings.extend(prefab_bindings),
+                Scan::Glb(glb_primitives) => primitives.push((PathBuf::from(file.path), glb_primitives)),
             }
         }
 
+        // link materials to the meshes prefabs render them on, once every guid is known
+        let mut mesh_links: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for binding in bindings {
+            // only .glb meshes can be shown
+            let Some(mesh) = guids.path(&binding.mesh).filter(|mesh| mesh.ends_with(".glb")) else {
+                continue;
+            };
+            for material in binding.materials.iter().filter_map(|material| guids.path(material)) {
+                mesh_links.entry(material.to_string()).or_default().insert(mesh.to_string());
+            }
+        }
+        // the material names the primitives of each .glb are drawn with
+        let mut used_by: BTreeMap<String, Vec<MeshPrimitive>> = BTreeMap::new();
+        for (mesh, primitives) in primitives {
+            for primitive in primitives {
+                let Some(material) = primitive.material else {
+                    continue;
+                };
+                used_by.entry(material).or_default().push(MeshPrimitive {
+                    mesh: mesh.to_string_lossy().to_string(),
+                    mesh_index: primitive.mesh,
+                    primitive: primitive.primitive,
+                });
+            }
+        }
+        for primitives in used_by.values_mut() {
+            primitives.sort();
+        }
         println!("{} materials found", material_files.len());
+        println!("{} materials used by .glb primitives", used_by.len());
+        self.used_by = used_by;
+        println!("{} materials linked to meshes by {} prefabs", mesh_links.len(), prefab_files.len());
+        self.mesh_links = mesh_links.into_iter().map(|(material, meshes)| (material, meshes.into_iter().collect())).collect();
         self.total_materials = material_files.len();
+        self.duplicate_names = duplicate_names(&material_files);
+        if !self.duplicate_names.is_empty() {
+            println!("{} names shared by several materials, their folders get the material's GUID appended", self.duplicate_names.len());
+        }
         self.materials = material_files;
         self.mesh_files = mesh_files;
-        self.guids = vec;
+        println!("{} assets indexed", guids.len());
+        self.guids = guids;
         self.step = 1;
-        Ok(())
-        
+        Ok(ImportStatus::Completed)
     }
-    fn parse_material(&self, material: PathBuf) -> Result<(), Box<dyn Error>>{
-        let lines = std::fs::read_to_string(material.clone())?;
+    /// Work out the folder, files and manifest of a material without touching the output,
+    fn plan_material(&self, material: &Path) -> Result<MaterialPlan, MatGenError> {
+        let text = std::fs::read_to_string(material).map_err(MatGenError::io(material))?;
+        let unity_material = UnityMaterial::parse(&text).map_err(MatGenError::parse(material))?;
         let mut names: Vec<String> =  Vec::new();
         let mut guids: Vec<Option<String>> =  Vec::new();
-        for line in lines.clone().lines() {
-            if line.trim_start().starts_with("m_Shader:") {
-                names.push("Shader".to_string());
-                let mut texture = line.trim()
-                    .strip_prefix("m_Shader: {fileID: 4800000, guid: ");
-                if texture.is_none() {
-                    guids.push(None);
-                    continue;
-                }
-                texture = texture.unwrap().strip_suffix(", type: 3}");  
-                if texture.is_none() {
-                    guids.push(None);
-                    continue;
-                }
-                guids.push(Some(texture.unwrap().to_string()));
-            }
-/*             else if line.starts_with("m_Script:") {
-                names.push("Script".to_string());
-                let mut texture = line.trim()
-                    .strip_prefix("m_Script: {fileID: 11500000, guid: ");
-                if texture.is_none() {
-                    guids.push(None);
-                    continue;
-                }
-                texture = texture.unwrap().strip_suffix(", type: 3}");
-                if texture.is_none() {
-                    guids.push(None);
-                    continue;
-                }
-                guids.push(Some(texture.unwrap().to_string()));
-            } */
-        }
-        // get lines between "m_TexEnvs:" and "m_Floats:"
 
-        let start = lines.find("m_TexEnvs:").unwrap() + "m_TexEnvs:".len();
-        let end = lines.find("m_Floats:").unwrap();
-        let texture_environments_block = &lines[start..end];
+        names.push("Shader".to_string());
+        guids.push(unity_material.shader.guid.clone());
 
-        for line in texture_environments_block.lines() {
-            if line.is_empty() {
+        for (name, tex_env) in &unity_material.tex_envs {
+            // {fileID: 0} is an empty slot
+            if tex_env.texture.is_null() {
                 continue;
             }
+            names.push(name.clone());
+            guids.push(tex_env.texture.guid.clone());
+        }
 
-            if line.trim_start().starts_with('-') {
-                let name = line.trim()
-                .strip_prefix('-').unwrap().to_string()
-                .strip_suffix(':').unwrap().to_string();
-                names.push(name);
+        // match the guids to the paths
+        let mut textures: Vec<(String, String)> = Vec::new();
+        // (slot, file name) pairs handed to the shader profile
+        let mut slots: Vec<(String, String)> = Vec::new();
+        // texture slots whose guid is not in the export, listed in the import report
+        let mut unresolved = BTreeMap::new();
+        let mut texture_settings = BTreeMap::new();
+        for (name, guid) in names.iter().zip(guids.iter()) {
+            if guid.is_none() {
+                continue;
             }
-
-            if line.trim_start().starts_with("m_Texture:") {
-                let mut texture = line.trim()
-                    .strip_prefix("m_Texture: {fileID: 2800000, guid: ");
-                if texture.is_none() {
-                    guids.push(None);
-                    continue;
-                }
-                texture = texture.unwrap().strip_suffix(", type: 3}");
-                if texture.is_none() {
-                    guids.push(None);
-                    continue;
+            let guid = guid.clone().unwrap();
+            let Some(path) = self.guids.path(&guid) else {
+                // shaders usually come from packages that aren't part of the export
+                if name != "Shader" {
+                    unresolved.insert(name.clone(), guid);
                 }
-                guids.push(Some(texture.unwrap().to_string()));
-
+                continue;
+            };
+            let path = path.to_string();
+            let extention = path.split('.').next_back().unwrap();
+            let file_name = format!("{}.{}",name.clone(), extention);
+            // a meta that can't be read leaves the texture to Unity's defaults
+            if let Ok(Some(settings)) = TextureImportSettings::from_texture(&path) {
+                texture_settings.insert(file_name.clone(), settings);
             }
+            slots.push((name.clone(), file_name.clone()));
+            textures.push((file_name, path.clone()));
         }
-        let mut removed = 0;
-        for ((i, _name), guid) in names.clone().iter().enumerate().zip(guids.iter()) {
-            if guid.is_none() {
-                names.remove(i-removed);
-                removed += 1;
+        // a material folder named after the material file, with the .mat, mesh and textures in it
+        let material_name = material.file_stem().unwrap_or_default().to_string_lossy();
+        let material_name = material_name.as_ref();
+        let folder = self.material_folder(material);
+        let mut files = BTreeMap::new();
+        files.insert(format!("{}.mat", material_name), material.to_string_lossy().to_string());
+
+        let mut mesh = None;
+        let mut mesh_match = None;
+        if let Some((path, method)) = self.find_mesh(material, &unity_material.name) {
+            let mesh_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            files.insert(mesh_name.clone(), path.to_string_lossy().to_string());
+            mesh = Some(mesh_name);
+            mesh_match = Some(method);
+        }
+        files.extend(textures);
+
+        let profile = self.shader_profiles.select(&unity_material);
+        let mut manifest = MaterialManifest::from_unity(&unity_material);
+        manifest.guid = self.guids.guid_of(material).map(str::to_string);
+        manifest.shader_name = self.shader_name(&unity_material.shader);
+        manifest.source = material.to_string_lossy().to_string();
+        manifest.mesh = mesh;
+        manifest.mesh_match = mesh_match;
+        manifest.used_by = self.used_by.get(&unity_material.name).cloned().unwrap_or_default();
+        manifest.files = files;
+        manifest.texture_settings = texture_settings;
+        manifest.shader_profile = profile.name.clone();
+        manifest.textures = profile.assign(&slots);
+        // shaders the profile doesn't know still mark their normal map in its import settings
+        if manifest.textures.normal.is_none() {
+            manifest.textures.normal = slots
+                .iter()
+                .find(|(_, file)| manifest.texture_settings_of(file).normal_map)
+                .map(|(slot, file)| TextureRef { slot: slot.clone(), file: file.clone() });
+        }
+        // textures the viewer can't load get a converted copy in the material folder
+        for texture in manifest.textures.slots_mut().into_iter().flatten() {
+            let Some((stem, extension)) = texture.file.rsplit_once('.') else {
                 continue;
+            };
+            if let Some(converted_extension) = converted_extension(extension) {
+                let converted = format!("{}.{}", stem, converted_extension);
+                manifest.converted.insert(converted.clone(), texture.file.clone());
+                texture.file = converted;
             }
-            //println!("{}: {}", name, guid.clone().unwrap());
+        }
+        manifest.unresolved = unresolved;
+        manifest.in_place = self.output_mode == OutputMode::ManifestOnly;
+        Ok(MaterialPlan {
+            folder,
+            manifest,
+            packing: profile.packing,
+            unity_material,
+        })
+    }
+    fn parse_material(&self, material: PathBuf) -> Result<(), MatGenError>{
+        let MaterialPlan { folder, mut manifest, packing, unity_material } = self.plan_material(&material)?;
+        if folder.exists() {
+            std::fs::remove_dir_all(&folder).map_err(MatGenError::io(&folder))?;
+        }
+        std::fs::create_dir_all(&folder).map_err(MatGenError::io(&folder))?;
+        for (name, source) in &manifest.files {
+            self.place_file(Path::new(source), &folder.join(name))?;
         }
 
-        // match the guids to the paths
-        //println!("{},{}", names.len(), guids.len());
-        let mut textures: Vec<(String, String)> = Vec::new();
-        for (name, guid) in names.iter().zip(guids.iter()) {
-            if guid.is_none() {
+        let baked = bake_orm(&folder, &manifest, packing, &unity_material)
+            .map_err(|e| MatGenError::Bake { path: material.clone(), message: e.to_string() })?;
+        if let Some(baked) = baked {
+            // the baked texture is sampled like the mask it came from, but always holds linear data
+            let mask = manifest.textures.metallic_roughness.as_ref().or(manifest.textures.occlusion.as_ref());
+            let settings = mask.map(|mask| manifest.texture_settings_of(&mask.file)).unwrap_or_default();
+            manifest.texture_settings.insert(baked.file.clone(), TextureImportSettings { srgb: false, ..settings });
+            manifest.textures.metallic_roughness = Some(baked.clone());
+            manifest.textures.occlusion = Some(baked);
+        }
+        let baked = bake_normal_map(&folder, &manifest, self.flip_normal_green)
+            .map_err(|e| MatGenError::Bake { path: material.clone(), message: e.to_string() })?;
+        if let Some(baked) = baked {
+            let normal = manifest.textures.normal.as_ref().map(|normal| manifest.texture_settings_of(&normal.file));
+            let settings = TextureImportSettings { srgb: false, normal_map: true, ..normal.unwrap_or_default() };
+            manifest.texture_settings.insert(baked.file.clone(), settings);
+            manifest.textures.normal = Some(baked);
+        }
+        self.convert_textures(&folder, &mut manifest);
+        if self.texture_cache {
+            self.cache_textures(&folder, &mut manifest);
+        }
+        if manifest.textures.metallic_roughness.is_some() {
+            // the packed texture holds metallic and roughness, don't scale it down
+            manifest.properties.metallic = 1.0;
+            manifest.properties.perceptual_roughness = 1.0;
+        }
+        manifest.write(&folder).map_err(MatGenError::write(folder.join(MANIFEST_FILE_NAME)))?;
+        // on disk before the journal records the material as done
+        sync_folder(&folder).map_err(MatGenError::io(&folder))?;
+        Ok(())
+    }
+    /// Name of the shader `shader` references, see `shader_name`.
+    fn shader_name(&self, shader: &FileRef) -> Option<String> {
+        let key = (shader.guid.clone()?, shader.file_id);
+        if let Some(name) = self.shader_names.lock().unwrap().get(&key) {
+            return name.clone();
+        }
+        let name = shader_name(shader, &self.guids);
+        self.shader_names.lock().unwrap().insert(key, name.clone());
+        name
+    }
+    /// Write the converted textures of `manifest` still in use into `folder`. A texture that can't be converted
+    /// is left out of its slots and listed as an error, the rest of the material is imported.
+    fn convert_textures(&self, folder: &Path, manifest: &mut MaterialManifest) {
+        let used: BTreeSet<String> = manifest.textures.slots_mut().into_iter().flatten().map(|texture| texture.file.clone()).collect();
+        manifest.converted.retain(|file, _| used.contains(file));
+        for (file, original) in manifest.converted.clone() {
+            let Some(source) = manifest.files.get(&original) else {
                 continue;
+            };
+            if let Err(e) = convert_texture(Path::new(source), &folder.join(&file)) {
+                let error = MatGenError::Convert { path: PathBuf::from(source), message: e.to_string() };
+                self.errors.lock().unwrap().push(error);
+                manifest.converted.remove(&file);
+                for slot in manifest.textures.slots_mut() {
+                    if slot.as_ref().is_some_and(|texture| texture.file == file) {
+                        *slot = None;
+                    }
+                }
             }
-            let guid = guid.clone().unwrap();
-            let path = self.guids.iter().find(|(g, _)| g == &guid);
-            if path.is_none() {
-                println!("{}: not found", name);
+        }
+    }
+    /// Write the KTX2 copies of the textures of `manifest` into `folder`. A texture that can't be cached
+    /// is listed as an error and left to the viewer as it is.
+    fn cache_textures(&self, folder: &Path, manifest: &mut MaterialManifest) {
+        let used: BTreeSet<String> = manifest.textures.slots_mut().into_iter().flatten().map(|texture| texture.file.clone()).collect();
+        let folder_name = folder.to_string_lossy();
+        for file in used {
+            let source = manifest.file_path(&folder_name, &file);
+            if !is_cacheable(&source) {
                 continue;
             }
-            let path = path.unwrap().1.clone();
-            let extention = path.split('.').last().unwrap();
-            let name = format!("{}.{}",name.clone(), extention);
-            textures.push((name.clone(), path.clone()));
-            //println!("{}: {}", name, path);
-        }
-        // create a material folder with the same name as the material file, and copy the textures there
-        let material_path = material.clone();
-        let material_name = material.file_name().unwrap().to_str().unwrap();
-        let material_name = material_name.strip_suffix(".mat").unwrap();
-
-        // find mesh in self.mesh_files
-        let split = material_name.split('_').collect::<Vec<&str>>();
-        if split.len() <= 1 {
-            return Ok(());
-        }
-        let contain_name: String = format!("_{}",material_name.split('_').collect::<Vec<&str>>()[1]);
-        
-        let mesh_path = self.mesh_files.iter().find(|path| path.to_str().unwrap().contains(contain_name.as_str()));
-
-        if Path::new(&format!("{}/assets/{}", self.output_directory, material_name)).exists() {
-            std::fs::remove_dir_all(&format!("{}/assets/{}", self.output_directory, material_name))?;
-        }
-        std::fs::create_dir_all(format!("{}/assets/{}", self.output_directory,material_name))?;
-        // copy .mat file to the material folder
-        std::fs::copy(material_path.clone(), format!("{}/assets/{}/{}.mat", self.output_directory, material_name, material_name))?;
-
-        if let Some(path)  = mesh_path {
-            let mesh_name = path.file_name().unwrap().to_str().unwrap();
-            //println!("{}: {}", material_name, mesh_name);
-            //println!("{:?}", mesh_path);
-            std::fs::copy(path, format!("{}/assets/{}/{}", self.output_directory, material_name, mesh_name))?;
-        }
-        for (name, path) in textures {
-            //println!("{}: {}", name, path);
-            // copy the texture to the material folder
-            std::fs::copy(path, format!("{}/assets/{}/{}", self.output_directory, material_name, name))?;
-            
+            let cached = format!("{}.{}", file.rsplit_once('.').map_or(file.as_str(), |(stem, _)| stem), CACHE_EXTENSION);
+            let settings = manifest.texture_settings_of(&file);
+            match cache_texture(Path::new(&source), &folder.join(&cached), &settings) {
+                Ok(true) => {
+                    manifest.cached.insert(file, cached);
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    let error = MatGenError::Convert { path: PathBuf::from(source), message: e.to_string() };
+                    self.errors.lock().unwrap().push(error);
+                }
+            }
+        }
+    }
+    /// Put `source` at `destination` according to the output mode, falling back to a copy when it can't be linked.
+    fn place_file(&self, source: &Path, destination: &Path) -> Result<(), MatGenError> {
+        let linked = match self.output_mode {
+            OutputMode::Copy => false,
+            OutputMode::Hardlink => std::fs::hard_link(source, destination).is_ok(),
+            OutputMode::Symlink => std::fs::canonicalize(source)
+                .and_then(|source| symlink_file(source, destination))
+                .is_ok(),
+            OutputMode::ManifestOnly => return Ok(()),
         };
+        if !linked {
+            std::fs::copy(source, destination).map_err(MatGenError::io(source))?;
+        }
         Ok(())
+    }
+    /// Find the mesh for a material, the first one a prefab renders it with, the first `.glb`
+    /// with a primitive using a glTF material named `name`, or else, for material files named
+    /// `<prefix>_<mesh>_...`, the first `.glb` containing `_<mesh>`.
+    fn find_mesh(&self, material: &Path, name: &str) -> Option<(PathBuf, MeshMatch)> {
+        if let Some(mesh) = self.mesh_links.get(material.to_string_lossy().as_ref()).and_then(|meshes| meshes.first()) {
+            return Some((PathBuf::from(mesh), MeshMatch::Prefab));
+        }
+        if let Some(primitive) = self.used_by.get(name).and_then(|primitives| primitives.first()) {
+            return Some((PathBuf::from(&primitive.mesh), MeshMatch::Gltf));
+        }
+        let material_name = material.file_stem()?.to_string_lossy();
+        if !follows_naming(&material_name) {
+            return None;
+        }
+        let contain_name = format!("_{}", material_name.split('_').nth(1)?);
+        self.mesh_files.iter()
+            .find(|path| path.to_string_lossy().contains(contain_name.as_str()))
+            .map(|path| (path.clone(), MeshMatch::Name))
+    }
+    /// Output folder of a material, named after its file, with its GUID appended when
+    /// `.mat` files in other directories have the same name.
+    fn material_folder(&self, material: &Path) -> PathBuf {
+        let name = material.file_stem().unwrap_or_default().to_string_lossy();
+        if !self.duplicate_names.contains(name.as_ref()) {
+            return PathBuf::from(format!("{}/assets/{}", self.output_directory, name));
+        }
+        // materials without a .meta get a digest of their path instead
+        let suffix = match self.guids.guid_of(material) {
+            Some(guid) => guid.to_string(),
+            None => blake3::hash(material.to_string_lossy().as_bytes()).to_hex()[..32].to_string(),
+        };
+        PathBuf::from(format!("{}/assets/{}_{}", self.output_directory, name, suffix))
+    }
+    /// The manifest in a material's output folder, `None` when there is none or it belongs to another material.
+    fn read_manifest(&self, material: &Path) -> Option<MaterialManifest> {
+        MaterialManifest::read(self.material_folder(material))
+            .ok()
+            .filter(|manifest| Path::new(&manifest.source) == material)
+    }
+    /// Output folders of materials that no longer exist in the export, or that moved to another folder.
+    fn removed_outputs(&self) -> Vec<PathBuf> {
+        let sources: BTreeSet<String> = self.materials.iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        let Ok(folders) = std::fs::read_dir(format!("{}/assets", self.output_directory)) else {
+            return Vec::new();
+        };
+        folders
+            .flatten()
+            .map(|folder| folder.path())
+            .filter(|folder| {
+                MaterialManifest::read(folder).is_ok_and(|manifest| {
+                    !sources.contains(&manifest.source) || self.material_folder(Path::new(&manifest.source)) != *folder
+                })
+            })
+            .collect()
+    }
+    /// When updating an earlier import, mark materials whose `.mat`, textures, shader and mesh are unchanged
+    /// and whose missing textures are still missing as done.
+    fn skip_unchanged(&mut self) {
+        let Some(changed) = &self.changed_files else {
+            return;
+        };
+        self.materials.sort_by_key(|path| path.clone());
+        // the index of the last import, to find shaders that were moved or deleted since
+        let previous_guids = GuidIndex::load(format!("{}/{}", self.output_directory, GUID_INDEX_FILE_NAME)).unwrap_or_default();
+        let file_changed = |source: &str| changed.contains(source) || changed.contains(&format!("{}.meta", source));
 
+        let mut progress = Progress::default();
+        for (i, material) in self.materials.iter().enumerate() {
+            if self.unfinished.contains(material) {
+                continue;
+            }
+            let Some(manifest) = self.read_manifest(material) else {
+                continue;
+            };
+            // any copied file or its .meta changing means the material has to be redone
+            let dependency_changed = manifest.files.values().any(|source| file_changed(source));
+            // the shader gives the material its shader name
+            let shader_changed = manifest.shader_guid.as_deref().is_some_and(|guid| {
+                [self.guids.path(guid), previous_guids.path(guid)].into_iter().flatten().any(file_changed)
+            });
+            let unresolved_found = manifest.unresolved.values().any(|guid| self.guids.path(guid).is_some());
+            let mesh_source = manifest.mesh.as_ref().and_then(|mesh| manifest.files.get(mesh));
+            let mesh_changed = self.find_mesh(material, &manifest.name).map(|(path, _)| path.to_string_lossy().to_string()).as_ref() != mesh_source;
+            let used_by_changed = self.used_by.get(&manifest.name).map_or(&[][..], Vec::as_slice) != manifest.used_by.as_slice();
+            if !dependency_changed && !shader_changed && !unresolved_found && !mesh_changed && !used_by_changed {
+                progress.complete(i);
+            }
+        }
+        println!("{} of {} materials unchanged", progress.current + progress.done_ahead.len(), self.materials.len());
+        self.progress = Mutex::new(progress);
     }
-    fn parse_materials(&mut self) -> Result<(), Box<dyn Error>> {
+    fn parse_materials(&mut self) -> Result<ImportStatus, MatGenError> {
         println!("Parsing materials");
-        let _ = std::fs::remove_dir_all(self.output_directory.clone());
-
+        let resuming = {
+            let progress = self.progress.lock().unwrap();
+            progress.current > 0 || !progress.done_ahead.is_empty()
+        };
+        let link_journal_path = Path::new(&self.output_directory).join(LINK_JOURNAL_FILE_NAME);
+        if !resuming {
+            // everything but what the linker read, kept to read only new or changed files next time
+            for entry in std::fs::read_dir(&self.output_directory).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path == link_journal_path {
+                    continue;
+                }
+                let _ = match path.is_dir() {
+                    true => std::fs::remove_dir_all(&path),
+                    false => std::fs::remove_file(&path),
+                };
+            }
+        }
+        std::fs::create_dir_all(&self.output_directory).map_err(MatGenError::io(&self.output_directory))?;
+        let guids_path = format!("{}/{}", self.output_directory, GUID_INDEX_FILE_NAME);
+        self.guids.save(&guids_path).map_err(MatGenError::write(&guids_path))?;
+        if self.file_hashes.is_empty() {
+            self.scan_hashes(&FileHashes::default());
+        }
         self.materials.sort_by_key(|path| path.clone());
+        self.hash = Some(self.file_hashes.digest().to_hex());
+        // the log has to say the import is unfinished before the hashes of the export are replaced,
+        // or a crash in between would pass the last import off as up to date
+        self.log_progress()?;
+        let journal_path = Path::new(&self.output_directory).join(JOURNAL_FILE_NAME);
+        let mut journal = Journal::create(&journal_path).map_err(MatGenError::io(&journal_path))?;
+        let hashes_path = format!("{}/{}", self.output_directory, FILE_HASHES_FILE_NAME);
+        self.file_hashes.save(&hashes_path).map_err(MatGenError::write(&hashes_path))?;
+
         // bar with msg of current material and total materials
-        let bar = ProgressBar::new(self.materials.len() as u64);
-        let style = ProgressStyle::with_template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} ({eta}) {msg}");
-        bar.set_style(style?);
-        self.hash = Some(hash_directory(self.directory.clone())?.to_hex());
-        for i in self.current_material..self.materials.len() {
-            let material = self.materials[i].clone();
-            if self.is_terminating.load(Ordering::Relaxed) {
-                eprintln!("Interrupted! Exiting gracefully...");
-                self.log_progress()?; // Make sure to log progress before exiting.
-                exit(0);
-            }
-            Materials::parse_material(self, material.to_path_buf())?;
-            //println!("{}", self.total_materials);
-            let log_interval = 10f64.powi((self.total_materials as f64).log10() as i32 - 1);
+        let bar = progress_bar(self.materials.len());
+
+        let pending: Vec<usize> = {
+            let progress = self.progress.lock().unwrap();
+            (0..self.materials.len()).filter(|i| !progress.is_done(*i)).collect()
+        };
+        bar.inc((self.materials.len() - pending.len()) as u64);
 
-            if self.current_material % log_interval as usize == 0 || self.completed { 
-                //println!("{} of {} materials processed", self.current_material, self.total_materials);
-                self.log_progress()?; // Log progress after processing each 10th material.
+        // workers take the next pending material, the progress is only updated here as results come in
+        let this = &*self;
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel::<(usize, Result<(), MatGenError>)>();
+        std::thread::scope(|scope| -> Result<(), MatGenError> {
+            for _ in 0..this.threads.min(pending.len()) {
+                let sender = sender.clone();
+                let (next, pending) = (&next, &pending);
+                scope.spawn(move || loop {
+                    if this.is_terminating.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let Some(&i) = pending.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let result = this.parse_material(this.materials[i].clone());
+                    if sender.send((i, result)).is_err() {
+                        break;
+                    }
+                });
             }
+            drop(sender);
 
-            bar.set_message(
-                material.file_name()
-                .unwrap().to_str().unwrap()
-                .strip_suffix(".mat").unwrap().to_string());
-            bar.inc(1);
-            self.current_material += 1;
+            for (i, result) in receiver {
+                // a material that failed is skipped, it is retried once its files change
+                if let Err(error) = result {
+                    bar.println(format!("Skipped {}", error));
+                    this.errors.lock().unwrap().push(error);
+                }
+                journal.append(&this.materials[i]).map_err(MatGenError::io(&journal_path))?;
+                this.progress.lock().unwrap().complete(i);
+                bar.set_message(this.materials[i].file_stem().unwrap_or_default().to_string_lossy().to_string());
+                bar.inc(1);
+            }
+            Ok(())
+        })?;
+        if self.is_terminating.load(Ordering::Relaxed) {
+            eprintln!("Interrupted! Exiting gracefully...");
+            self.log_progress()?; // Make sure to log progress before exiting.
+            return Ok(ImportStatus::Interrupted);
         }
         self.completed = true;
         self.step = 2;
         self.log_progress()?;
+        drop(journal);
+        std::fs::remove_file(&journal_path).map_err(MatGenError::io(&journal_path))?;
         bar.finish();
-        Ok(())
+        Ok(ImportStatus::Completed)
+    }
+    /// Collect what the import left out from the manifests of the imported materials.
+    fn report(&self) -> ImportReport {
+        let mut report = ImportReport::default();
+        let mut used = BTreeSet::new();
+        for material in &self.materials {
+            // materials that failed have no manifest, they are listed as errors
+            let Some(manifest) = self.read_manifest(material) else {
+                continue;
+            };
+            for (slot, guid) in &manifest.unresolved {
+                report.unresolved_textures.push(UnresolvedTexture {
+                    material: manifest.name.clone(),
+                    slot: slot.clone(),
+                    guid: guid.clone(),
+                });
+            }
+            if manifest.mesh.is_none() {
+                report.materials_without_mesh.push(manifest.name.clone());
+            }
+            used.extend(manifest.files.into_values());
+        }
+        report.unused_textures = self.guids.iter()
+            .filter(|(_, entry)| entry.importer == "TextureImporter" && !used.contains(&entry.path))
+            .map(|(_, entry)| entry.path.clone())
+            .collect();
+        report.unused_textures.sort();
+        report.unused_meshes = self.mesh_files.iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .filter(|path| !used.contains(path))
+            .collect();
+        report.unused_meshes.sort();
+        report.errors = self.errors.lock().unwrap().iter().map(|error| error.to_string()).collect();
+        report
     }
-    fn log_progress(&self) -> Result<(), Box<dyn Error>>{
-        // if dir is None, dir is the current directory
-        let file_path = format!("{}/log.toml", self.output_directory.clone());
-        std::fs::File::create(&file_path)?;
-        let mut file = std::fs::OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(file_path)?;
+    fn log_progress(&self) -> Result<(), MatGenError>{
+        let file_path = Path::new(&self.output_directory).join(LOG_FILE_NAME);
         let header = Header {
             date_time: chrono::Local::now().to_string(),
             version: env!("CARGO_PKG_VERSION").to_string(),
+            output_version: OUTPUT_VERSION,
             directory: self.directory.clone(),
             completed: self.completed,
-            hash: self.hash.unwrap().to_string(),
+            output_mode: self.output_mode.as_str().to_string(),
+            flip_normal_green: self.flip_normal_green,
+            texture_cache: self.texture_cache,
+            shader_profiles: self.shader_profiles.digest(),
+            hash: self.hash.map(|hash| hash.to_string()).unwrap_or_default(),
         };
 
-        let toml = Toml {
+        let progress = self.progress.lock().unwrap();
+        let toml = ImportLog {
+            schema: SCHEMA_VERSION,
             header,
             step: self.step,
-            current: self.current_material,
+            current: progress.current,
+            done_ahead: progress.done_ahead.iter().copied().collect(),
             total: self.total_materials,
             data: Data {
                 materials: self.materials.clone(),
-                guids: self.guids.clone(),
-            }
+                meshes: self.mesh_files.clone(),
+                mesh_links: self.mesh_links.clone(),
+                used_by: self.used_by.clone(),
+            },
+            migrated_from: None,
         };
-        let toml = toml::to_string(&toml)?;
-        file.write_all(toml.as_bytes())?;
-        
-        Ok(())
-        
+        toml.write(&file_path).map_err(MatGenError::write(&file_path))
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Toml {
-    header: Header,
-    step: usize,
-    current: usize,
-    total: usize,
-    data: Data,
+#[cfg(unix)]
+fn symlink_file(source: PathBuf, destination: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, destination)
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Header {
-    date_time: String,
-    version: String,
-    directory: String,
-    completed: bool,
-    hash: String,
-} 
+#[cfg(windows)]
+fn symlink_file(source: PathBuf, destination: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, destination)
+}
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Data {
-    materials: Vec<PathBuf>,
-    guids: Vec<(String, String)>,
-    
+/// Read what the linker needs from the `.meta`, `.prefab` or `.glb` file at `path`.
+fn scan_file(path: &Path) -> Result<Scan, MatGenError> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("meta") => index_meta(path).map(|(guid, entry)| Scan::Meta { guid, entry }),
+        Some("prefab") => read_prefab(path).map(Scan::Prefab),
+        _ => read_primitives(path)
+            .map(Scan::Glb)
+            .map_err(|e| MatGenError::Parse { path: path.to_path_buf(), message: e.to_string() }),
+    }
+}
+
+/// Bar with the elapsed time, the count and the current file as message.
+/// Whether a file that couldn't be read was already reported, the hashes and the linker walk the same export.
+fn is_recorded(errors: &[MatGenError], error: &MatGenError) -> bool {
+    let MatGenError::Io { path, .. } = error else {
+        return false;
+    };
+    errors.iter().any(|e| matches!(e, MatGenError::Io { path: recorded, .. } if recorded == path))
+}
+
+fn progress_bar(len: usize) -> ProgressBar {
+    let bar = ProgressBar::new(len as u64);
+    let style = ProgressStyle::with_template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} ({eta}) {msg}");
+    bar.set_style(style.unwrap_or_else(|_| ProgressStyle::default_bar()));
+    bar
+}
+
+/// Read the mesh to material bindings of the `.prefab` file at `path`.
+fn read_prefab(path: &Path) -> Result<Vec<MeshBinding>, MatGenError> {
+    let text = std::fs::read_to_string(path).map_err(MatGenError::io(path))?;
+    parse_prefab(&text).map_err(MatGenError::parse(path))
+}
+
+/// File names of the `.mat` files in `materials` that more than one has.
+fn duplicate_names(materials: &[PathBuf]) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    materials
+        .iter()
+        .map(|material| material.file_stem().unwrap_or_default().to_string_lossy().to_string())
+        .filter(|name| !names.insert(name.clone()))
+        .collect()
+}
+
+/// Material files named `<prefix>_<mesh>_...` can be matched to a mesh by their name.
+fn follows_naming(material_name: &str) -> bool {
+    material_name.contains('_')
+}
+
+/// Read the GUID and importer of the `.meta` file at `path`.
+fn index_meta(path: &Path) -> Result<(String, GuidEntry), MatGenError> {
+    let text = std::fs::read_to_string(path).map_err(MatGenError::io(path))?;
+    let file_path = path.to_string_lossy();
+    let file_name = file_path.strip_suffix(".meta").unwrap_or(&file_path);
+    GuidIndex::parse_meta(&text, file_name).map_err(MatGenError::parse(path))
+}
+
+/// What `Materials::plan_material` works out for a material, everything but the baked textures.
+struct MaterialPlan {
+    folder: PathBuf,
+    manifest: MaterialManifest,
+    packing: Packing,
+    unity_material: UnityMaterial,
+}
+
+/// An error of the import, with the file it happened on.
+#[derive(Debug)]
+pub enum MatGenError {
+    /// Reading or copying a file failed.
+    Io { path: PathBuf, source: io::Error },
+    /// A `.mat` or `.meta` file is malformed.
+    Parse { path: PathBuf, message: String },
+    /// The textures of a material could not be baked.
+    Bake { path: PathBuf, message: String },
+    /// A texture could not be converted into a format the viewer loads.
+    Convert { path: PathBuf, message: String },
+    /// A manifest, index or the log could not be written.
+    Write { path: PathBuf, message: String },
+    /// The SIGINT and SIGTERM handlers could not be registered.
+    Signal(io::Error),
+    /// The log names a step this version doesn't know.
+    InvalidStep(usize),
+    /// The import log is corrupt or was written by a newer version.
+    Log { path: PathBuf, message: String },
+}
+
+impl MatGenError {
+    fn io<P: AsRef<Path>>(path: P) -> impl FnOnce(io::Error) -> MatGenError {
+        let path = path.as_ref().to_path_buf();
+        move |source| MatGenError::Io { path, source }
+    }
+    fn parse<P: AsRef<Path>, E: Display>(path: P) -> impl FnOnce(E) -> MatGenError {
+        let path = path.as_ref().to_path_buf();
+        move |e| MatGenError::Parse { path, message: e.to_string() }
+    }
+    fn write<P: AsRef<Path>, E: Display>(path: P) -> impl FnOnce(E) -> MatGenError {
+        let path = path.as_ref().to_path_buf();
+        move |e| MatGenError::Write { path, message: e.to_string() }
+    }
 }
 
-fn hash_directory<P: AsRef<Path>>(path: P) -> io::Result<Hash> {
-    let mut hasher = Hasher::new();
-    for entry in WalkDir::new(path) {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            let mut file = File::open(entry.path())?;
-            let mut contents = Vec::new();
-            file.read_to_end(&mut contents)?;
-            hasher.update(&contents);
-            hasher.update(entry.file_name().to_string_lossy().as_bytes());
+impl Display for MatGenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatGenError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            MatGenError::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+            MatGenError::Bake { path, message } => write!(f, "{}: failed to bake textures: {}", path.display(), message),
+            MatGenError::Convert { path, message } => write!(f, "{}: failed to convert: {}", path.display(), message),
+            MatGenError::Write { path, message } => write!(f, "{}: failed to write: {}", path.display(), message),
+            MatGenError::Signal(e) => write!(f, "failed to register signal handlers: {}", e),
+            MatGenError::InvalidStep(step) => write!(f, "invalid step {} in the import log", step),
+            MatGenError::Log { path, message } => {
+                write!(f, "{}: unusable import log, pass --discard-log to start over: {}", path.display(), message)
+            }
         }
     }
-    Ok(hasher.finalize())
 }
 
-fn hash_from_hex_str(hex_str: &str) -> io::Result<Hash> {
-    if hex_str.len() == 64 {
-        let bytes: Vec<u8> = hex::decode(hex_str)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
-        let byte_array: [u8; 32] = <std::vec::Vec<u8> as std::convert::TryInto<[u8; 32]>>::try_into(bytes)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Hex string must represent 32 bytes."))?;
-        Ok(Hash::from(byte_array))
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "Hex string must represent 32 bytes.",
-        ))
-    }
-}
\ No newline at end of file
+impl Error for MatGenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MatGenError::Io { source, .. } | MatGenError::Signal(source) => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Mesh to material links read from the `MeshFilter`s and `MeshRenderer`s of `.prefab` files.
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use serde_yaml::Value;
+
+use crate::unity_yaml::{parse_documents, FileRef};
+
+const MESH_RENDERER_CLASS_ID: i64 = 23;
+const MESH_FILTER_CLASS_ID: i64 = 33;
+/// Holds its mesh in `m_Mesh` itself, no `MeshFilter` needed.
+const SKINNED_MESH_RENDERER_CLASS_ID: i64 = 137;
+
+/// A mesh and the materials it is rendered with, by GUID.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MeshBinding {
+    pub mesh: String,
+    pub materials: Vec<String>,
+}
+
+/// Find which mesh is rendered with which materials, pairing the components of each `GameObject`.
+pub fn parse_prefab(text: &str) -> Result<Vec<MeshBinding>, Box<dyn Error>> {
+    // keyed by the fileID of the GameObject the components are on
+    let mut meshes: BTreeMap<i64, String> = BTreeMap::new();
+    let mut materials: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+    for document in parse_documents(text)? {
+        // stripped documents are only placeholders for objects of nested prefabs
+        if document.stripped {
+            continue;
+        }
+        let Some(game_object) = document.body.get("m_GameObject").and_then(FileRef::from_value) else {
+            continue;
+        };
+        let mesh = || document.body.get("m_Mesh").and_then(FileRef::from_value).and_then(|mesh| mesh.guid);
+        let renderer_materials = || match document.body.get("m_Materials") {
+            Some(Value::Sequence(list)) => list
+                .iter()
+                .filter_map(FileRef::from_value)
+                .filter_map(|material| material.guid)
+                .collect(),
+            _ => Vec::new(),
+        };
+        match document.class_id {
+            MESH_FILTER_CLASS_ID => {
+                if let Some(mesh) = mesh() {
+                    meshes.insert(game_object.file_id, mesh);
+                }
+            }
+            MESH_RENDERER_CLASS_ID => {
+                materials.insert(game_object.file_id, renderer_materials());
+            }
+            SKINNED_MESH_RENDERER_CLASS_ID => {
+                if let Some(mesh) = mesh() {
+                    meshes.insert(game_object.file_id, mesh);
+                }
+                materials.insert(game_object.file_id, renderer_materials());
+            }
+            _ => {}
+        }
+    }
+    Ok(meshes
+        .into_iter()
+        .filter_map(|(game_object, mesh)| {
+            let materials = materials.remove(&game_object)?;
+            Some(MeshBinding { mesh, materials })
+        })
+        .collect())
+}
@@ -0,0 +1,274 @@
+//! Reads the merged image Photoshop stores at the end of `.psd` files, for textures kept as PSD.
+//!
+//! Only what textures use is supported: grayscale and RGB, 8 or 16 bits per channel, raw or RLE compressed.
+use std::error::Error;
+
+use image::{DynamicImage, ImageBuffer, Pixel};
+
+const PSD_SIGNATURE: &[u8; 4] = b"8BPS";
+const GRAYSCALE: u16 = 1;
+const RGB: u16 = 3;
+const RAW: u16 = 0;
+const RLE: u16 = 1;
+
+/// Decode the merged image of the `.psd` file in `data`.
+pub fn read_psd(data: &[u8]) -> Result<DynamicImage, Box<dyn Error>> {
+    let mut reader = Reader { data, position: 0 };
+    if reader.bytes(4)? != PSD_SIGNATURE {
+        return Err("not a PSD file".into());
+    }
+    if reader.u16()? != 1 {
+        return Err("large documents (PSB) aren't supported".into());
+    }
+    reader.bytes(6)?;
+    let channels = reader.u16()? as usize;
+    let height = reader.u32()?;
+    let width = reader.u32()?;
+    let depth = reader.u16()?;
+    let color_mode = reader.u16()?;
+    // color mode data, image resources, layers and masks
+    for _ in 0..3 {
+        let length = reader.u32()? as usize;
+        reader.bytes(length)?;
+    }
+
+    let bytes_per_sample = match depth {
+        8 => 1,
+        16 => 2,
+        _ => return Err(format!("{} bits per channel aren't supported", depth).into()),
+    };
+    let too_large = || "PSD image is too large";
+    let row_length = (width as usize).checked_mul(bytes_per_sample).ok_or_else(too_large)?;
+    let rows = channels.checked_mul(height as usize).ok_or_else(too_large)?;
+    let planes_length = rows.checked_mul(row_length).ok_or_else(too_large)?;
+    // the channels are stored one after another, row by row
+    let planes = match reader.u16()? {
+        RAW => reader.bytes(planes_length)?.to_vec(),
+        RLE => {
+            // the header can't be trusted with the allocations: a row length takes 2 bytes,
+            // and PackBits turns 2 bytes into 128 at most
+            let mut lengths = Vec::with_capacity(rows.min(reader.remaining() / 2));
+            for _ in 0..rows {
+                lengths.push(reader.u16()? as usize);
+            }
+            let mut planes = Vec::with_capacity(planes_length.min(reader.remaining().saturating_mul(64)));
+            for length in lengths {
+                unpack_bits(reader.bytes(length)?, row_length, &mut planes)?;
+            }
+            planes
+        }
+        compression => return Err(format!("compression {} isn't supported", compression).into()),
+    };
+    let plane_length = height as usize * row_length;
+    let sample = |channel: usize, pixel: usize| -> u16 {
+        let offset = channel * plane_length + pixel * bytes_per_sample;
+        match bytes_per_sample {
+            1 => planes[offset] as u16,
+            _ => u16::from_be_bytes([planes[offset], planes[offset + 1]]),
+        }
+    };
+    let used_channels = match (color_mode, channels) {
+        (GRAYSCALE, 1..) => channels.min(2),
+        (RGB, 3..) => channels.min(4),
+        _ => return Err(format!("color mode {} with {} channels isn't supported", color_mode, channels).into()),
+    };
+    // extra channels past the alpha are masks, left out
+    let pixels = width as usize * height as usize;
+    let samples: Vec<u16> = (0..pixels)
+        .flat_map(|pixel| (0..used_channels).map(move |channel| (channel, pixel)))
+        .map(|(channel, pixel)| sample(channel, pixel))
+        .collect();
+    if bytes_per_sample == 2 {
+        return Ok(match used_channels {
+            1 => DynamicImage::ImageLuma16(buffer(width, height, samples)?),
+            2 => DynamicImage::ImageLumaA16(buffer(width, height, samples)?),
+            3 => DynamicImage::ImageRgb16(buffer(width, height, samples)?),
+            _ => DynamicImage::ImageRgba16(buffer(width, height, samples)?),
+        });
+    }
+    let samples: Vec<u8> = samples.into_iter().map(|sample| sample as u8).collect();
+    Ok(match used_channels {
+        1 => DynamicImage::ImageLuma8(buffer(width, height, samples)?),
+        2 => DynamicImage::ImageLumaA8(buffer(width, height, samples)?),
+        3 => DynamicImage::ImageRgb8(buffer(width, height, samples)?),
+        _ => DynamicImage::ImageRgba8(buffer(width, height, samples)?),
+    })
+}
+
+type Buffer<P> = ImageBuffer<P, Vec<<P as Pixel>::Subpixel>>;
+
+fn buffer<P: Pixel>(width: u32, height: u32, samples: Vec<P::Subpixel>) -> Result<Buffer<P>, Box<dyn Error>> {
+    ImageBuffer::from_raw(width, height, samples).ok_or_else(|| "PSD image data is too short".into())
+}
+
+/// Decode a PackBits compressed row of `length` bytes onto `output`.
+fn unpack_bits(mut packed: &[u8], length: usize, output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let end = output.len() + length;
+    while output.len() < end {
+        let (&header, rest) = packed.split_first().ok_or("RLE row is too short")?;
+        packed = rest;
+        match header as i8 {
+            // copy the next n + 1 bytes
+            count @ 0.. => {
+                let count = count as usize + 1;
+                output.extend_from_slice(packed.get(..count).ok_or("RLE row is too short")?);
+                packed = &packed[count..];
+            }
+            -128 => {}
+            // repeat the next byte 1 - n times
+            count => {
+                let (&byte, rest) = packed.split_first().ok_or("RLE row is too short")?;
+                packed = rest;
+                output.extend(std::iter::repeat_n(byte, (1 - count as isize) as usize));
+            }
+        }
+    }
+    output.truncate(end);
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let bytes = self
+            .data
+            .get(self.position..)
+            .and_then(|rest| rest.get(..length))
+            .ok_or("unexpected end of the PSD file")?;
+        self.position += length;
+        Ok(bytes)
+    }
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(channels: u16, width: u32, height: u32, depth: u16, color_mode: u16, compression: u16) -> Vec<u8> {
+        let mut data = PSD_SIGNATURE.to_vec();
+        data.extend(1u16.to_be_bytes());
+        data.extend([0; 6]);
+        data.extend(channels.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend(width.to_be_bytes());
+        data.extend(depth.to_be_bytes());
+        data.extend(color_mode.to_be_bytes());
+        // empty color mode data, image resources, layers and masks
+        data.extend([0; 12]);
+        data.extend(compression.to_be_bytes());
+        data
+    }
+
+    /// PackBits rows: runs of the same byte repeated, the rest copied as literals.
+    fn pack_rows(planes: &[u8], row_length: usize) -> Vec<u8> {
+        let rows: Vec<Vec<u8>> = planes
+            .chunks(row_length)
+            .map(|row| match row {
+                [first, rest @ ..] if rest.iter().all(|byte| byte == first) && !rest.is_empty() => {
+                    vec![(1 - row.len() as i8) as u8, *first]
+                }
+                _ => [&[(row.len() - 1) as u8][..], row].concat(),
+            })
+            .collect();
+        let mut data: Vec<u8> = rows.iter().flat_map(|row| (row.len() as u16).to_be_bytes()).collect();
+        data.extend(rows.concat());
+        data
+    }
+
+    #[test]
+    fn reads_raw_grayscale() {
+        let mut data = header(1, 2, 2, 8, GRAYSCALE, RAW);
+        data.extend([0, 64, 128, 255]);
+        let image = read_psd(&data).unwrap().into_luma8();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.into_raw(), [0, 64, 128, 255]);
+    }
+
+    #[test]
+    fn reads_raw_16_bit_rgb() {
+        let mut data = header(3, 2, 1, 16, RGB, RAW);
+        // the red, green and blue planes of two pixels
+        for sample in [0xFFFFu16, 0x0102, 0x8000, 0x0000, 0x1234, 0xABCD] {
+            data.extend(sample.to_be_bytes());
+        }
+        let DynamicImage::ImageRgb16(image) = read_psd(&data).unwrap() else {
+            panic!("not read as 16-bit RGB");
+        };
+        assert_eq!(image.into_raw(), [0xFFFF, 0x8000, 0x1234, 0x0102, 0x0000, 0xABCD]);
+    }
+
+    #[test]
+    fn reads_rle_rgba_without_extra_channels() {
+        // red, green, blue, alpha and a mask channel left out, two rows of three pixels each
+        let planes = [[10, 20, 30, 40, 40, 40], [1, 1, 1, 2, 2, 2], [0, 0, 0, 0, 0, 0], [255, 128, 0, 9, 9, 9], [7; 6]];
+        let mut data = header(5, 3, 2, 8, RGB, RLE);
+        data.extend(pack_rows(&planes.concat(), 3));
+        let DynamicImage::ImageRgba8(image) = read_psd(&data).unwrap() else {
+            panic!("not read as RGBA");
+        };
+        assert_eq!(image.get_pixel(0, 0).0, [10, 1, 0, 255]);
+        assert_eq!(image.get_pixel(2, 0).0, [30, 1, 0, 0]);
+        assert_eq!(image.get_pixel(1, 1).0, [40, 2, 0, 9]);
+    }
+
+    #[test]
+    fn reads_rle_16_bit_grayscale() {
+        let samples = [0x0001u16, 0xFF00, 0x7777, 0x7777];
+        let planes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_be_bytes()).collect();
+        let mut data = header(1, 2, 2, 16, GRAYSCALE, RLE);
+        data.extend(pack_rows(&planes, 4));
+        let DynamicImage::ImageLuma16(image) = read_psd(&data).unwrap() else {
+            panic!("not read as 16-bit grayscale");
+        };
+        assert_eq!(image.into_raw(), samples);
+    }
+
+    #[test]
+    fn fails_on_truncated_data() {
+        let mut raw = header(3, 4, 4, 8, RGB, RAW);
+        raw.extend([0; 47]);
+        assert!(read_psd(&raw).is_err());
+
+        let mut rle = header(1, 4, 2, 8, GRAYSCALE, RLE);
+        rle.extend(pack_rows(&[1, 2, 3, 4, 5, 6, 7, 8], 4));
+        for length in [rle.len() - 1, rle.len() - 6, 30] {
+            assert!(read_psd(&rle[..length]).is_err());
+        }
+        assert!(read_psd(&header(1, 1, 1, 8, GRAYSCALE, RAW)[..20]).is_err());
+    }
+
+    #[test]
+    fn fails_on_oversized_images() {
+        for compression in [RAW, RLE] {
+            for (channels, width, height) in [(4, u32::MAX, u32::MAX), (u16::MAX, 1 << 20, 1 << 20), (3, 1 << 16, 1 << 16)] {
+                let mut data = header(channels, width, height, 16, RGB, compression);
+                data.extend([0; 64]);
+                assert!(read_psd(&data).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn fails_on_unsupported_formats() {
+        let mut cmyk = header(4, 1, 1, 8, 4, RAW);
+        cmyk.extend([0; 4]);
+        assert!(read_psd(&cmyk).is_err());
+        let mut one_bit = header(1, 8, 1, 1, GRAYSCALE, RAW);
+        one_bit.extend([0]);
+        assert!(read_psd(&one_bit).is_err());
+        assert!(read_psd(b"not a PSD").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Names of the shaders materials use, as Unity shows them in the material inspector:
+//! the `Shader "Name"` of a `.shader` file, the path of a `.shadergraph`, or a built-in shader's name.
+use std::ffi::OsStr;
+use std::path::Path;
+
+use crate::guid_index::GuidIndex;
+use crate::shader_profile::BUILTIN_SHADER_GUID;
+use crate::unity_yaml::FileRef;
+
+/// Shader graphs without a path in their graph data are listed under this one.
+const DEFAULT_SHADER_GRAPH_PATH: &str = "Shader Graphs";
+
+/// fileIDs of well-known built-in shaders.
+const BUILTIN_SHADERS: &[(i64, &str)] = &[
+    (7, "Legacy Shaders/Diffuse"),
+    (45, "Standard (Specular setup)"),
+    (46, "Standard"),
+    (47, "Autodesk Interactive"),
+    (106, "Skybox/Procedural"),
+    (10750, "Unlit/Transparent"),
+    (10751, "Unlit/Transparent Cutout"),
+    (10752, "Unlit/Texture"),
+    (10753, "Sprites/Default"),
+    (10754, "Sprites/Mask"),
+    (10755, "Unlit/Color"),
+    (10770, "UI/Default"),
+];
+
+/// Name of the shader `shader` references, `None` when it is neither a known built-in shader
+/// nor a shader file of the export.
+pub fn shader_name(shader: &FileRef, guids: &GuidIndex) -> Option<String> {
+    let guid = shader.guid.as_deref()?;
+    if guid == BUILTIN_SHADER_GUID {
+        return BUILTIN_SHADERS
+            .iter()
+            .find(|(file_id, _)| *file_id == shader.file_id)
+            .map(|(_, name)| name.to_string());
+    }
+    let path = Path::new(guids.path(guid)?);
+    let text = std::fs::read_to_string(path).ok()?;
+    match path.extension().and_then(OsStr::to_str) {
+        Some("shader") => declared_name(&text),
+        Some("shadergraph") => {
+            let stem = path.file_stem()?.to_string_lossy();
+            let graph_path = shader_graph_path(&text).unwrap_or_else(|| DEFAULT_SHADER_GRAPH_PATH.to_string());
+            Some(match graph_path.trim_matches('/') {
+                "" => stem.to_string(),
+                graph_path => format!("{}/{}", graph_path, stem),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The name in `Shader "Name" { ... }`, the first statement of ShaderLab files outside of comments.
+fn declared_name(text: &str) -> Option<String> {
+    let mut rest = text.trim_start_matches('\u{feff}');
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.split_once('\n').map_or("", |(_, after)| after);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map_or("", |(_, after)| after);
+        } else {
+            break;
+        }
+    }
+    let name = rest.strip_prefix("Shader")?.trim_start().strip_prefix('"')?;
+    name.split_once('"').map(|(name, _)| name.to_string())
+}
+
+/// `m_Path` of the graph data. Shader graphs are one JSON object, or several in a row since Shader Graph 10.
+fn shader_graph_path(text: &str) -> Option<String> {
+    serde_json::Deserializer::from_str(text)
+        .into_iter::<serde_json::Value>()
+        .map_while(Result::ok)
+        .find_map(|object| object.get("m_Path").and_then(serde_json::Value::as_str).map(str::to_string))
+}
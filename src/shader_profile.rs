@@ -0,0 +1,193 @@
+//! Tables deciding which `m_TexEnvs` slot feeds which `StandardMaterial` texture, per shader.
+//!
+//! Profiles for HDRP Lit, URP Lit and Built-in Standard are built in. More can be loaded from a TOML file:
+//!
+//! ```toml
+//! [[profile]]
+//! name = "My Toon Shader"
+//! shader_guids = ["0123456789abcdef0123456789abcdef"]
+//! base_color = ["_Albedo", "_MainTex"]
+//! normal = ["_Normal"]
+//! metallic_roughness = ["_MetallicGlossMap"]
+//! packing = "MetallicGloss"
+//! ```
+use std::error::Error;
+use std::path::Path;
+
+use crate::manifest::{TextureRef, TextureSlots};
+use crate::unity_yaml::UnityMaterial;
+
+/// GUID Unity uses for shaders compiled into the editor, told apart by their fileID.
+pub const BUILTIN_SHADER_GUID: &str = "0000000000000000f000000000000000";
+
+/// How a shader packs metallic, smoothness and occlusion into its textures.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Packing {
+    /// Already Bevy's layout: R occlusion, G roughness, B metallic.
+    #[default]
+    Orm,
+    /// HDRP `_MaskMap`: R metallic, G occlusion, B detail mask, A smoothness.
+    HdrpMask,
+    /// Built-in and URP: `_MetallicGlossMap` with R metallic and A smoothness, `_OcclusionMap` in G.
+    MetallicGloss,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct ShaderProfile {
+    pub name: String,
+    /// `m_Shader` GUIDs this profile is used for.
+    #[serde(default)]
+    pub shader_guids: Vec<String>,
+    /// `m_Shader` fileIDs of built-in shaders this profile is used for.
+    #[serde(default)]
+    pub builtin_file_ids: Vec<i64>,
+    /// Used for unknown shaders that have any of these keywords and at least one of the slots below.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Candidate slot names for each texture, the first one present in the material wins.
+    #[serde(default)]
+    pub base_color: Vec<String>,
+    #[serde(default)]
+    pub normal: Vec<String>,
+    #[serde(default)]
+    pub metallic_roughness: Vec<String>,
+    #[serde(default)]
+    pub occlusion: Vec<String>,
+    #[serde(default)]
+    pub emissive: Vec<String>,
+    #[serde(default)]
+    pub packing: Packing,
+}
+
+impl ShaderProfile {
+    fn matches_shader(&self, material: &UnityMaterial) -> bool {
+        let Some(guid) = &material.shader.guid else {
+            return false;
+        };
+        if guid == BUILTIN_SHADER_GUID {
+            return self.builtin_file_ids.contains(&material.shader.file_id);
+        }
+        self.shader_guids.contains(guid)
+    }
+    fn matches_keywords(&self, material: &UnityMaterial) -> bool {
+        self.keywords.iter().any(|keyword| material.has_keyword(keyword))
+            && self.slots().any(|slot| material.tex_env(slot).is_some())
+    }
+    fn slots(&self) -> impl Iterator<Item = &String> {
+        self.base_color
+            .iter()
+            .chain(&self.normal)
+            .chain(&self.metallic_roughness)
+            .chain(&self.occlusion)
+            .chain(&self.emissive)
+    }
+    /// Pick a texture for every `StandardMaterial` slot out of the `(slot, file)` pairs that resolved.
+    pub fn assign(&self, textures: &[(String, String)]) -> TextureSlots {
+        let pick = |candidates: &[String]| {
+            candidates.iter().find_map(|candidate| {
+                textures
+                    .iter()
+                    .find(|(slot, _)| slot == candidate)
+                    .map(|(slot, file)| TextureRef { slot: slot.clone(), file: file.clone() })
+            })
+        };
+        TextureSlots {
+            base_color: pick(&self.base_color),
+            normal: pick(&self.normal),
+            metallic_roughness: pick(&self.metallic_roughness),
+            occlusion: pick(&self.occlusion),
+            emissive: pick(&self.emissive),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ShaderProfiles {
+    #[serde(rename = "profile", default)]
+    pub profiles: Vec<ShaderProfile>,
+}
+
+impl Default for ShaderProfiles {
+    fn default() -> Self {
+        ShaderProfiles::builtin()
+    }
+}
+
+impl ShaderProfiles {
+    pub fn builtin() -> ShaderProfiles {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        ShaderProfiles {
+            profiles: vec![
+                ShaderProfile {
+                    name: "HDRP Lit".to_string(),
+                    shader_guids: names(&["6e4ae4064600d784cac1e41a9e6f2e59"]),
+                    keywords: names(&["_MASKMAP", "_NORMALMAP_TANGENT_SPACE"]),
+                    base_color: names(&["_BaseColorMap"]),
+                    normal: names(&["_NormalMap"]),
+                    metallic_roughness: names(&["_MaskMap"]),
+                    occlusion: names(&["_MaskMap"]),
+                    emissive: names(&["_EmissiveColorMap"]),
+                    packing: Packing::HdrpMask,
+                    ..Default::default()
+                },
+                ShaderProfile {
+                    name: "URP Lit".to_string(),
+                    shader_guids: names(&["933532a4fcc9baf4fa0491de14d08ed7"]),
+                    keywords: names(&["_METALLICSPECGLOSSMAP"]),
+                    base_color: names(&["_BaseMap", "_MainTex"]),
+                    normal: names(&["_BumpMap"]),
+                    metallic_roughness: names(&["_MetallicGlossMap"]),
+                    occlusion: names(&["_OcclusionMap"]),
+                    emissive: names(&["_EmissionMap"]),
+                    packing: Packing::MetallicGloss,
+                    ..Default::default()
+                },
+                ShaderProfile {
+                    name: "Built-in Standard".to_string(),
+                    // Standard and Standard (Specular setup)
+                    builtin_file_ids: vec![46, 45],
+                    keywords: names(&["_METALLICGLOSSMAP"]),
+                    base_color: names(&["_MainTex"]),
+                    normal: names(&["_BumpMap"]),
+                    metallic_roughness: names(&["_MetallicGlossMap"]),
+                    occlusion: names(&["_OcclusionMap"]),
+                    emissive: names(&["_EmissionMap"]),
+                    packing: Packing::MetallicGloss,
+                    ..Default::default()
+                },
+                // Custom shaders and Shader Graphs, using whichever common name is present
+                ShaderProfile {
+                    name: "Generic".to_string(),
+                    base_color: names(&["BaseColor", "_BaseColorMap", "_BaseMap", "_MainTex", "_Albedo", "_Diffuse"]),
+                    normal: names(&["Normal", "_NormalMap", "_BumpMap", "_Normal"]),
+                    metallic_roughness: names(&["ORM", "_ORM"]),
+                    occlusion: names(&["ORM", "_ORM"]),
+                    emissive: names(&["Emission", "_EmissiveColorMap", "_EmissionMap"]),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+    /// Load profiles from a TOML file. They are tried before the built-in ones.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ShaderProfiles, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let mut profiles: ShaderProfiles = toml::from_str(&text)?;
+        profiles.profiles.extend(ShaderProfiles::builtin().profiles);
+        Ok(profiles)
+    }
+    /// Hash of the profiles, an import redoes every material when it differs from the last one's.
+    pub fn digest(&self) -> String {
+        let profiles = serde_json::to_vec(self).unwrap_or_default();
+        blake3::hash(&profiles).to_hex().to_string()
+    }
+    /// Find the profile for a material, by shader first and then by keywords,
+    /// falling back to the last profile.
+    pub fn select(&self, material: &UnityMaterial) -> &ShaderProfile {
+        self.profiles
+            .iter()
+            .find(|profile| profile.matches_shader(material))
+            .or_else(|| self.profiles.iter().find(|profile| profile.matches_keywords(material)))
+            .or(self.profiles.last())
+            .expect("no shader profiles")
+    }
+}
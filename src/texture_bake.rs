@@ -0,0 +1,324 @@
+//! Converts Unity's metallic / smoothness / occlusion packings into the texture layout Bevy samples:
+//! occlusion in R, perceptual roughness in G and metallic in B.
+//! Normal maps packed in Unity's DXT5nm layout are unpacked into XYZ the same way.
+use std::error::Error;
+use std::path::Path;
+
+use image::{imageops::FilterType, DynamicImage, Rgb, Rgb32FImage, Rgba, Rgba32FImage, RgbaImage};
+
+use crate::manifest::{MaterialManifest, TextureRef};
+use crate::shader_profile::Packing;
+use crate::texture_convert::open_texture;
+use crate::unity_yaml::UnityMaterial;
+
+pub const BAKED_ORM_FILE_NAME: &str = "baked_orm.png";
+pub const BAKED_NORMAL_FILE_NAME: &str = "baked_normal.png";
+
+/// Keyword of Built-in and URP materials that keep smoothness in the alpha of the base map.
+const SMOOTHNESS_FROM_ALBEDO: &str = "_SMOOTHNESS_TEXTURE_ALBEDO_CHANNEL_A";
+
+/// Bake an ORM texture into `folder` for the textures of `manifest`, read from their sources.
+///
+/// Returns the reference to the baked texture, to be used for both metallic/roughness and occlusion,
+/// or `None` when the profile's packing needs no conversion.
+pub fn bake_orm(
+    folder: &Path,
+    manifest: &MaterialManifest,
+    packing: Packing,
+    material: &UnityMaterial,
+) -> Result<Option<TextureRef>, Box<dyn Error>> {
+    let slots = &manifest.textures;
+    let open = |texture: &Option<TextureRef>| -> Result<Option<RgbaImage>, Box<dyn Error>> {
+        match texture {
+            Some(texture) => {
+                let source = manifest
+                    .source_of(&texture.file)
+                    .ok_or_else(|| format!("{} is not a file of the material", texture.file))?;
+                Ok(Some(open_texture(Path::new(source))?.into_rgba8()))
+            }
+            None => Ok(None),
+        }
+    };
+    let float = |name: &str, default: f32| material.float(name).unwrap_or(default);
+    let lerp = |min: f32, max: f32, t: f32| min + (max - min) * t;
+
+    let baked = match packing {
+        Packing::Orm => return Ok(None),
+        Packing::HdrpMask => {
+            let Some(mask) = open(&slots.metallic_roughness)? else {
+                return Ok(None);
+            };
+            let metallic = (float("_MetallicRemapMin", 0.0), float("_MetallicRemapMax", 1.0));
+            let ao = (float("_AORemapMin", 0.0), float("_AORemapMax", 1.0));
+            let smoothness = (float("_SmoothnessRemapMin", 0.0), float("_SmoothnessRemapMax", 1.0));
+            map_pixels(&mask, |[r, g, _, a]| {
+                (
+                    lerp(ao.0, ao.1, g),
+                    1.0 - lerp(smoothness.0, smoothness.1, a),
+                    lerp(metallic.0, metallic.1, r),
+                )
+            })
+        }
+        Packing::MetallicGloss => {
+            let metallic_map = open(&slots.metallic_roughness)?;
+            let occlusion_map = open(&slots.occlusion)?;
+            let albedo_map = if material.has_keyword(SMOOTHNESS_FROM_ALBEDO) {
+                open(&slots.base_color)?
+            } else {
+                None
+            };
+            let Some((width, height)) = [&metallic_map, &occlusion_map, &albedo_map]
+                .into_iter()
+                .flatten()
+                .map(|image| image.dimensions())
+                .next()
+            else {
+                return Ok(None);
+            };
+            let resize = |image: Option<RgbaImage>| {
+                image.map(|image| {
+                    if image.dimensions() == (width, height) {
+                        image
+                    } else {
+                        image::imageops::resize(&image, width, height, FilterType::Triangle)
+                    }
+                })
+            };
+            let (metallic_map, occlusion_map, albedo_map) =
+                (resize(metallic_map), resize(occlusion_map), resize(albedo_map));
+
+            // Built-in scales texture smoothness by _GlossMapScale, URP by _Smoothness
+            let smoothness_scale = material.float("_GlossMapScale").or(material.float("_Smoothness")).unwrap_or(1.0);
+            let smoothness_scalar = material.float("_Smoothness").or(material.float("_Glossiness")).unwrap_or(0.5);
+            let metallic_scalar = float("_Metallic", 0.0);
+            let occlusion_strength = float("_OcclusionStrength", 1.0);
+
+            let mut baked = RgbaImage::new(width, height);
+            for (x, y, pixel) in baked.enumerate_pixels_mut() {
+                let sample = |image: &Option<RgbaImage>| image.as_ref().map(|image| to_unit(image.get_pixel(x, y)));
+                let metallic_sample = sample(&metallic_map);
+                let metallic = metallic_sample.map_or(metallic_scalar, |[r, ..]| r);
+                let smoothness = match (sample(&albedo_map), metallic_sample) {
+                    (Some([.., a]), _) | (None, Some([.., a])) => a * smoothness_scale,
+                    (None, None) => smoothness_scalar,
+                };
+                let occlusion = sample(&occlusion_map).map_or(1.0, |[_, g, ..]| lerp(1.0, g, occlusion_strength));
+                *pixel = to_orm(occlusion, 1.0 - smoothness, metallic);
+            }
+            baked
+        }
+    };
+    baked.save(folder.join(BAKED_ORM_FILE_NAME))?;
+
+    let slot = slots
+        .metallic_roughness
+        .as_ref()
+        .or(slots.occlusion.as_ref())
+        .map(|texture| texture.slot.clone())
+        .unwrap_or_default();
+    Ok(Some(TextureRef {
+        slot,
+        file: BAKED_ORM_FILE_NAME.to_string(),
+    }))
+}
+
+/// Bake the normal map of `manifest` into `folder` in the layout Bevy samples: X, Y and Z in RGB, Y up.
+///
+/// Unity compresses normal maps as DXT5nm, X in alpha and Y in green with red at 1, and exports often keep
+/// that layout; Z is then reconstructed from X and Y. `flip_green` flips Y, for maps authored with Y down.
+/// Returns `None` when the normal map is already in Bevy's layout.
+pub fn bake_normal_map(
+    folder: &Path,
+    manifest: &MaterialManifest,
+    flip_green: bool,
+) -> Result<Option<TextureRef>, Box<dyn Error>> {
+    let Some(normal) = &manifest.textures.normal else {
+        return Ok(None);
+    };
+    let source = manifest
+        .source_of(&normal.file)
+        .ok_or_else(|| format!("{} is not a file of the material", normal.file))?;
+    let image = open_texture(Path::new(source))?;
+    let high_precision = image.color().bytes_per_pixel() > image.color().channel_count();
+    let image = image.into_rgba32f();
+    let ag_packed = is_ag_packed(&image);
+    if !ag_packed && !flip_green {
+        return Ok(None);
+    }
+
+    let mut baked = Rgb32FImage::new(image.width(), image.height());
+    for (x, y, pixel) in baked.enumerate_pixels_mut() {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let (normal_x, normal_y, normal_z) = if ag_packed {
+            let (normal_x, normal_y) = (a * 2.0 - 1.0, g * 2.0 - 1.0);
+            (normal_x, normal_y, (1.0 - normal_x * normal_x - normal_y * normal_y).max(0.0).sqrt())
+        } else {
+            (r * 2.0 - 1.0, g * 2.0 - 1.0, b * 2.0 - 1.0)
+        };
+        let normal_y = if flip_green { -normal_y } else { normal_y };
+        *pixel = Rgb([normal_x, normal_y, normal_z].map(|channel| (channel * 0.5 + 0.5).clamp(0.0, 1.0)));
+    }
+    let baked = DynamicImage::ImageRgb32F(baked);
+    let path = folder.join(BAKED_NORMAL_FILE_NAME);
+    if high_precision {
+        baked.into_rgb16().save(path)?;
+    } else {
+        baked.into_rgb8().save(path)?;
+    }
+    Ok(Some(TextureRef {
+        slot: normal.slot.clone(),
+        file: BAKED_NORMAL_FILE_NAME.to_string(),
+    }))
+}
+
+/// DXT5nm keeps red at 1 and X in alpha, where normal maps in RGB have X in red.
+fn is_ag_packed(image: &Rgba32FImage) -> bool {
+    const NEAR_ONE: f32 = 0.98;
+    image.pixels().all(|pixel| pixel[0] >= NEAR_ONE) && image.pixels().any(|pixel| pixel[3] < NEAR_ONE)
+}
+
+fn map_pixels(image: &RgbaImage, convert: impl Fn([f32; 4]) -> (f32, f32, f32)) -> RgbaImage {
+    let mut baked = RgbaImage::new(image.width(), image.height());
+    for (x, y, pixel) in baked.enumerate_pixels_mut() {
+        let (occlusion, roughness, metallic) = convert(to_unit(image.get_pixel(x, y)));
+        *pixel = to_orm(occlusion, roughness, metallic);
+    }
+    baked
+}
+
+fn to_unit(pixel: &Rgba<u8>) -> [f32; 4] {
+    pixel.0.map(|channel| channel as f32 / 255.0)
+}
+
+fn to_orm(occlusion: f32, roughness: f32, metallic: f32) -> Rgba<u8> {
+    let byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgba([byte(occlusion), byte(roughness), byte(metallic), 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use image::{ImageBuffer, Rgba};
+
+    use super::*;
+
+    /// A material with `keywords` and `floats`, parsed like a `.mat` file.
+    fn material(keywords: &str, floats: &[(&str, f32)]) -> UnityMaterial {
+        let floats: String = floats.iter().map(|(name, value)| format!("    - {}: {}\n", name, value)).collect();
+        let text = format!(
+            "--- !u!21 &2100000\nMaterial:\n  m_Name: M_Test\n  m_ShaderKeywords: {}\n  m_SavedProperties:\n    m_Floats:\n{}",
+            keywords, floats
+        );
+        UnityMaterial::parse(&text).unwrap()
+    }
+
+    /// An empty folder of its own for each test.
+    fn folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("texture_bake_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// Save `image` into `folder` and make it a file of `manifest`, returns the reference to put in a slot.
+    fn add_texture(folder: &Path, manifest: &mut MaterialManifest, file: &str, image: DynamicImage) -> Option<TextureRef> {
+        let source = folder.join(format!("source_{}", file));
+        image.save(&source).unwrap();
+        manifest.files.insert(file.to_string(), source.to_string_lossy().to_string());
+        Some(TextureRef { slot: file.to_string(), file: file.to_string() })
+    }
+
+    fn rgba(pixel: [u8; 4]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba(pixel)))
+    }
+
+    fn baked(folder: &Path, file: &str) -> DynamicImage {
+        image::open(folder.join(file)).unwrap()
+    }
+
+    fn assert_near(actual: &[u16], expected: &[u16], tolerance: u16) {
+        let near = actual.iter().zip(expected).all(|(actual, expected)| actual.abs_diff(*expected) <= tolerance);
+        assert!(near && actual.len() == expected.len(), "{:?} isn't {:?}", actual, expected);
+    }
+
+    #[test]
+    fn bakes_hdrp_mask() {
+        let folder = folder("hdrp_mask");
+        let material = material("_MASKMAP", &[("_MetallicRemapMax", 0.2), ("_SmoothnessRemapMax", 0.5)]);
+        let mut manifest = MaterialManifest::from_unity(&material);
+        // metallic 1, occlusion 0.2, smoothness 0.8
+        manifest.textures.metallic_roughness = add_texture(&folder, &mut manifest, "Mask.png", rgba([255, 51, 0, 204]));
+        let baked_ref = bake_orm(&folder, &manifest, Packing::HdrpMask, &material).unwrap().unwrap();
+        assert_eq!(baked_ref, TextureRef { slot: "Mask.png".to_string(), file: BAKED_ORM_FILE_NAME.to_string() });
+        // occlusion as is, roughness 1 - 0.8 * 0.5, metallic remapped to 0.2
+        assert_eq!(baked(&folder, BAKED_ORM_FILE_NAME).into_rgba8().get_pixel(1, 1).0, [51, 153, 51, 255]);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn bakes_metallic_gloss_smoothness_from_albedo() {
+        let folder = folder("metallic_gloss");
+        let keyword = format!("_METALLICSPECGLOSSMAP {}", SMOOTHNESS_FROM_ALBEDO);
+        let material = material(&keyword, &[("_GlossMapScale", 1.0)]);
+        let mut manifest = MaterialManifest::from_unity(&material);
+        manifest.textures.metallic_roughness = add_texture(&folder, &mut manifest, "Metallic.png", rgba([102, 0, 0, 51]));
+        manifest.textures.base_color = add_texture(&folder, &mut manifest, "Albedo.png", rgba([9, 9, 9, 153]));
+        bake_orm(&folder, &manifest, Packing::MetallicGloss, &material).unwrap().unwrap();
+        // smoothness 0.6 from the albedo alpha, no occlusion map
+        assert_eq!(baked(&folder, BAKED_ORM_FILE_NAME).into_rgba8().get_pixel(0, 0).0, [255, 102, 102, 255]);
+
+        // without the keyword, smoothness is the metallic map's alpha, 0.2
+        let material = self::material("_METALLICSPECGLOSSMAP", &[("_GlossMapScale", 1.0)]);
+        bake_orm(&folder, &manifest, Packing::MetallicGloss, &material).unwrap().unwrap();
+        assert_eq!(baked(&folder, BAKED_ORM_FILE_NAME).into_rgba8().get_pixel(0, 0).0, [255, 204, 102, 255]);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn unpacks_dxt5nm_normal_maps() {
+        let folder = folder("dxt5nm");
+        let mut manifest = MaterialManifest::from_unity(&material("", &[]));
+        // X 0.6 in alpha, Y -0.6 in green, red at 1
+        manifest.textures.normal = add_texture(&folder, &mut manifest, "Normal.png", rgba([255, 51, 0, 204]));
+        bake_normal_map(&folder, &manifest, false).unwrap().unwrap();
+        let pixel = baked(&folder, BAKED_NORMAL_FILE_NAME).into_rgb8().get_pixel(0, 0).0;
+        // Z = sqrt(1 - 0.36 - 0.36)
+        assert_near(&pixel.map(u16::from), &[204, 51, 195], 1);
+
+        bake_normal_map(&folder, &manifest, true).unwrap().unwrap();
+        let pixel = baked(&folder, BAKED_NORMAL_FILE_NAME).into_rgb8().get_pixel(0, 0).0;
+        assert_near(&pixel.map(u16::from), &[204, 204, 195], 1);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn flips_green_of_rgb_normal_maps() {
+        let folder = folder("flip");
+        let mut manifest = MaterialManifest::from_unity(&material("", &[]));
+        manifest.textures.normal = add_texture(&folder, &mut manifest, "Normal.png", rgba([128, 64, 230, 255]));
+        // already in Bevy's layout
+        assert_eq!(bake_normal_map(&folder, &manifest, false).unwrap(), None);
+        bake_normal_map(&folder, &manifest, true).unwrap().unwrap();
+        let pixel = baked(&folder, BAKED_NORMAL_FILE_NAME).into_rgb8().get_pixel(1, 0).0;
+        assert_near(&pixel.map(u16::from), &[128, 191, 230], 1);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn keeps_16_bit_normal_maps_precise() {
+        let folder = folder("16_bit");
+        let mut manifest = MaterialManifest::from_unity(&material("", &[]));
+        // values 8 bits can't hold
+        let normal = ImageBuffer::from_pixel(2, 2, Rgba([65535u16, 13000, 0, 52000]));
+        manifest.textures.normal = add_texture(&folder, &mut manifest, "Normal.png", DynamicImage::ImageRgba16(normal));
+        bake_normal_map(&folder, &manifest, false).unwrap().unwrap();
+        let DynamicImage::ImageRgb16(baked) = baked(&folder, BAKED_NORMAL_FILE_NAME) else {
+            panic!("baked normal map isn't 16-bit");
+        };
+        let (x, y) = (52000.0 / 65535.0 * 2.0 - 1.0, 13000.0 / 65535.0 * 2.0 - 1.0);
+        let z = ((1.0f64 - x * x - y * y).sqrt() * 0.5 + 0.5) * 65535.0;
+        assert_near(&baked.get_pixel(0, 0).0, &[52000, 13000, z.round() as u16], 2);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
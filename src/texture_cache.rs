@@ -0,0 +1,337 @@
+//! The texture cache: a KTX2 copy of every texture the viewer samples, scaled down to the texture's
+//! `maxTextureSize`, with its mip chain, block compressed and supercompressed with Zstandard, so switching
+//! materials doesn't stall on decoding full size PNGs and the GPU samples compressed textures.
+//!
+//! Only 8-bit textures are cached, in the color space of their import settings: normal maps as BC5, opaque
+//! textures as BC1 and the others as BC3. Textures whose sides aren't multiples of 4 can't be block compressed
+//! and are cached as RGBA8. Textures with more bits per channel, 16-bit normal maps and HDR textures, are left
+//! to the viewer as they are so they keep their precision.
+use std::error::Error;
+use std::path::Path;
+
+use image::{imageops::FilterType, GenericImageView, Rgba32FImage};
+
+use crate::block_compression::{encode_bc1, encode_bc3, encode_bc5};
+use crate::texture_convert::open_texture;
+use crate::texture_settings::TextureImportSettings;
+
+pub const CACHE_EXTENSION: &str = "ktx2";
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+const VK_FORMAT_BC1_RGB_UNORM_BLOCK: u32 = 131;
+const VK_FORMAT_BC1_RGB_SRGB_BLOCK: u32 = 132;
+const VK_FORMAT_BC3_UNORM_BLOCK: u32 = 137;
+const VK_FORMAT_BC3_SRGB_BLOCK: u32 = 138;
+const VK_FORMAT_BC5_UNORM_BLOCK: u32 = 141;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+/// Identifier, header and index, up to the level index.
+const HEADER_LENGTH: usize = 80;
+const LEVEL_INDEX_ENTRY_LENGTH: usize = 24;
+
+/// How the levels of a cached texture are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CacheFormat {
+    /// Textures whose sides aren't multiples of 4.
+    Rgba8,
+    /// Opaque textures.
+    Bc1,
+    /// Textures with alpha.
+    Bc3,
+    /// Linear normal maps, red and green, the viewer rebuilds blue.
+    Bc5,
+}
+
+impl CacheFormat {
+    /// The format of a texture of the given size and settings, `rgba` being its full size level.
+    fn select(width: u32, height: u32, settings: &TextureImportSettings, rgba: &[u8]) -> CacheFormat {
+        if !width.is_multiple_of(4) || !height.is_multiple_of(4) {
+            CacheFormat::Rgba8
+        } else if settings.normal_map && !settings.srgb {
+            CacheFormat::Bc5
+        } else if rgba.chunks_exact(4).all(|pixel| pixel[3] == 255) {
+            CacheFormat::Bc1
+        } else {
+            CacheFormat::Bc3
+        }
+    }
+    fn vk_format(self, srgb: bool) -> u32 {
+        match (self, srgb) {
+            (CacheFormat::Rgba8, false) => VK_FORMAT_R8G8B8A8_UNORM,
+            (CacheFormat::Rgba8, true) => VK_FORMAT_R8G8B8A8_SRGB,
+            (CacheFormat::Bc1, false) => VK_FORMAT_BC1_RGB_UNORM_BLOCK,
+            (CacheFormat::Bc1, true) => VK_FORMAT_BC1_RGB_SRGB_BLOCK,
+            (CacheFormat::Bc3, false) => VK_FORMAT_BC3_UNORM_BLOCK,
+            (CacheFormat::Bc3, true) => VK_FORMAT_BC3_SRGB_BLOCK,
+            (CacheFormat::Bc5, _) => VK_FORMAT_BC5_UNORM_BLOCK,
+        }
+    }
+    /// The bytes of a level of the given size, from its RGBA8 pixels.
+    fn encode(self, rgba: Vec<u8>, width: u32, height: u32) -> Vec<u8> {
+        match self {
+            CacheFormat::Rgba8 => rgba,
+            CacheFormat::Bc1 => encode_bc1(&rgba, width, height),
+            CacheFormat::Bc3 => encode_bc3(&rgba, width, height),
+            CacheFormat::Bc5 => encode_bc5(&rgba, width, height),
+        }
+    }
+}
+
+/// Whether the texture at `path` may be cached, HDR textures aren't.
+pub fn is_cacheable(path: &str) -> bool {
+    !Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"))
+}
+
+/// Write the texture at `source` into the KTX2 file `destination`, as its import `settings` say.
+/// Returns whether it was cached, textures with more than 8 bits per channel aren't.
+pub fn cache_texture(source: &Path, destination: &Path, settings: &TextureImportSettings) -> Result<bool, Box<dyn Error>> {
+    let mut image = open_texture(source)?;
+    if image.color().bytes_per_pixel() > image.color().channel_count() {
+        return Ok(false);
+    }
+    let (width, height) = image.dimensions();
+    let max_size = settings.max_size.max(1);
+    if width.max(height) > max_size {
+        // Unity scales the longest side down to the max size, keeping the aspect ratio
+        let scale = max_size as f64 / width.max(height) as f64;
+        let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+        image = image.resize_exact(scaled(width), scaled(height), FilterType::Triangle);
+    }
+    let mut level = image.into_rgba32f();
+    if settings.srgb {
+        map_color(&mut level, srgb_to_linear);
+    }
+
+    let (width, height) = level.dimensions();
+    let mut format = None;
+    let mut levels = Vec::new();
+    loop {
+        let (level_width, level_height) = level.dimensions();
+        let rgba = to_bytes(&level, settings.srgb);
+        let format = *format.get_or_insert_with(|| CacheFormat::select(width, height, settings, &rgba));
+        levels.push(format.encode(rgba, level_width, level_height));
+        if !settings.mipmaps || (level_width == 1 && level_height == 1) {
+            break;
+        }
+        // mips are averaged in linear space, each side halved down to 1
+        let size = |side: u32| (side / 2).max(1);
+        level = image::imageops::resize(&level, size(level_width), size(level_height), FilterType::Triangle);
+    }
+    let format = format.unwrap_or(CacheFormat::Rgba8);
+    std::fs::write(destination, encode_ktx2(width, height, format, settings.srgb, &levels)?)?;
+    Ok(true)
+}
+
+fn map_color(image: &mut Rgba32FImage, convert: fn(f32) -> f32) {
+    for pixel in image.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = convert(*channel);
+        }
+    }
+}
+
+/// RGBA8 bytes of a level, its colors encoded in sRGB when `srgb`.
+fn to_bytes(level: &Rgba32FImage, srgb: bool) -> Vec<u8> {
+    let byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let mut bytes = Vec::with_capacity(level.len());
+    for pixel in level.pixels() {
+        let [r, g, b, a] = pixel.0;
+        let color = |value: f32| if srgb { linear_to_srgb(value) } else { value };
+        bytes.extend([byte(color(r)), byte(color(g)), byte(color(b)), byte(a)]);
+    }
+    bytes
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// A KTX2 file of `levels` in `format`, the full size one first, each compressed with Zstandard.
+fn encode_ktx2(width: u32, height: u32, format: CacheFormat, srgb: bool, levels: &[Vec<u8>]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let compressed = levels
+        .iter()
+        .map(|level| zstd::bulk::compress(level, zstd::DEFAULT_COMPRESSION_LEVEL))
+        .collect::<Result<Vec<_>, _>>()?;
+    let dfd = data_format_descriptor(format, srgb);
+    let dfd_offset = HEADER_LENGTH + levels.len() * LEVEL_INDEX_ENTRY_LENGTH;
+    let data_offset = dfd_offset + dfd.len();
+
+    let mut file = Vec::with_capacity(data_offset + compressed.iter().map(Vec::len).sum::<usize>());
+    file.extend(KTX2_IDENTIFIER);
+    // format, type size, width, height, depth, layers, faces, levels and supercompression
+    for value in [format.vk_format(srgb), 1, width, height, 0, 0, 1, levels.len() as u32, SUPERCOMPRESSION_ZSTD] {
+        file.extend(value.to_le_bytes());
+    }
+    // the data format descriptor, no key/value data or supercompression global data
+    for value in [dfd_offset as u32, dfd.len() as u32, 0, 0] {
+        file.extend(value.to_le_bytes());
+    }
+    file.extend(0u64.to_le_bytes());
+    file.extend(0u64.to_le_bytes());
+
+    // the smallest level is stored first
+    let mut offsets = vec![0; levels.len()];
+    let mut offset = data_offset;
+    for (index, level) in compressed.iter().enumerate().rev() {
+        offsets[index] = offset;
+        offset += level.len();
+    }
+    for ((level, compressed), offset) in levels.iter().zip(&compressed).zip(offsets) {
+        for value in [offset, compressed.len(), level.len()] {
+            file.extend((value as u64).to_le_bytes());
+        }
+    }
+    file.extend(dfd);
+    for level in compressed.iter().rev() {
+        file.extend(level);
+    }
+    Ok(file)
+}
+
+/// The basic data format descriptor of `format`, in sRGB or linear.
+fn data_format_descriptor(format: CacheFormat, srgb: bool) -> Vec<u8> {
+    const COLOR_MODEL_RGBSDA: u32 = 1;
+    const COLOR_MODEL_BC1A: u32 = 128;
+    const COLOR_MODEL_BC3: u32 = 130;
+    const COLOR_MODEL_BC5: u32 = 132;
+    const PRIMARIES_BT709: u32 = 1;
+    const TRANSFER_LINEAR: u32 = 1;
+    const TRANSFER_SRGB: u32 = 2;
+    const CHANNEL_ALPHA: u32 = 15;
+    const QUALIFIER_LINEAR: u32 = 0x10;
+    /// 4x4 texel blocks, each dimension minus one.
+    const BLOCK_4X4: u32 = 3 | (3 << 8);
+
+    /// Bit offset, bit length minus one, channel and upper value of a sample.
+    type Sample = (u32, u32, u32, u32);
+
+    // color model, texel block dimensions and samples
+    let (color_model, block, samples): (u32, u32, &[Sample]) = match format {
+        CacheFormat::Rgba8 => (COLOR_MODEL_RGBSDA, 0, &[(0, 7, 0, 255), (8, 7, 1, 255), (16, 7, 2, 255), (24, 7, CHANNEL_ALPHA, 255)]),
+        CacheFormat::Bc1 => (COLOR_MODEL_BC1A, BLOCK_4X4, &[(0, 63, 0, u32::MAX)]),
+        CacheFormat::Bc3 => (COLOR_MODEL_BC3, BLOCK_4X4, &[(0, 63, CHANNEL_ALPHA, u32::MAX), (64, 63, 0, u32::MAX)]),
+        CacheFormat::Bc5 => (COLOR_MODEL_BC5, BLOCK_4X4, &[(0, 63, 0, u32::MAX), (64, 63, 1, u32::MAX)]),
+    };
+    let srgb = srgb && format != CacheFormat::Bc5;
+    let block_length = 24 + 16 * samples.len() as u32;
+    let transfer = if srgb { TRANSFER_SRGB } else { TRANSFER_LINEAR };
+    let mut words = vec![
+        block_length + 4,
+        // vendor and descriptor type 0: Khronos basic
+        0,
+        2 | (block_length << 16),
+        color_model | (PRIMARIES_BT709 << 8) | (transfer << 16),
+        block,
+        // bytes per plane are left at 0 in supercompressed files
+        0,
+        0,
+    ];
+    for &(offset, length, channel, upper) in samples {
+        // alpha isn't sRGB encoded
+        let qualifiers = if srgb && channel == CHANNEL_ALPHA { QUALIFIER_LINEAR } else { 0 };
+        words.extend([offset | (length << 16) | ((channel | qualifiers) << 24), 0, 0, upper]);
+    }
+    words.into_iter().flat_map(u32::to_le_bytes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bevy::render::texture::{CompressedImageFormats, Image, ImageType};
+    use bevy::render::render_resource::TextureFormat;
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    /// Cache a `width` x `height` texture of `pixel` with `settings`, returns the KTX2 file.
+    fn cache(name: &str, width: u32, height: u32, pixel: [u8; 4], settings: TextureImportSettings) -> Vec<u8> {
+        let folder = std::env::temp_dir().join(format!("texture_cache_{}_{}", std::process::id(), name));
+        std::fs::create_dir_all(&folder).unwrap();
+        let source: PathBuf = folder.join("source.png");
+        RgbaImage::from_pixel(width, height, Rgba(pixel)).save(&source).unwrap();
+        let destination = folder.join("source.ktx2");
+        assert!(cache_texture(&source, &destination, &settings).unwrap());
+        let file = std::fs::read(&destination).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+        file
+    }
+
+    /// The offset, length and uncompressed length of every level in the level index.
+    fn level_index(file: &[u8], levels: usize) -> Vec<[usize; 3]> {
+        let entry = |start: usize| u64::from_le_bytes(file[start..start + 8].try_into().unwrap()) as usize;
+        (0..levels)
+            .map(|level| {
+                let start = HEADER_LENGTH + level * LEVEL_INDEX_ENTRY_LENGTH;
+                [entry(start), entry(start + 8), entry(start + 16)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn caches_opaque_textures_as_bc1_with_mips() {
+        let file = cache("bc1", 16, 8, [200, 100, 50, 255], TextureImportSettings::default());
+        let reader = ktx2::Reader::new(&file[..]).unwrap();
+        let header = reader.header();
+        assert_eq!(header.format, Some(ktx2::Format::BC1_RGB_SRGB_BLOCK));
+        assert_eq!((header.pixel_width, header.pixel_height, header.level_count), (16, 8, 5));
+        assert_eq!(header.supercompression_scheme, Some(ktx2::SupercompressionScheme::Zstandard));
+
+        // 16x8, 8x4, 4x2, 2x1 and 1x1, levels smaller than a block still take one
+        let blocks = [8, 2, 1, 1, 1];
+        let index = level_index(&file, 5);
+        for (level, (data, [offset, length, uncompressed])) in reader.levels().zip(index.iter().copied()).enumerate() {
+            assert_eq!(data, &file[offset..offset + length]);
+            assert_eq!(uncompressed, blocks[level] * 8);
+            assert_eq!(zstd::bulk::decompress(data, uncompressed).unwrap().len(), uncompressed);
+        }
+        // the smallest level comes first, right after the data format descriptor, and the largest ends the file
+        let dfd_end = u32::from_le_bytes(file[48..52].try_into().unwrap()) + u32::from_le_bytes(file[52..56].try_into().unwrap());
+        assert_eq!(index[4][0], dfd_end as usize);
+        for level in 0..4 {
+            assert_eq!(index[level + 1][0] + index[level + 1][1], index[level][0]);
+        }
+        assert_eq!(index[0][0] + index[0][1], file.len());
+
+        let image = Image::from_buffer(&file, ImageType::Extension("ktx2"), CompressedImageFormats::BC, true).unwrap();
+        assert_eq!(image.texture_descriptor.format, TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(image.texture_descriptor.mip_level_count, 5);
+        assert_eq!((image.texture_descriptor.size.width, image.texture_descriptor.size.height), (16, 8));
+        assert_eq!(image.data.len(), blocks.iter().sum::<usize>() * 8);
+    }
+
+    #[test]
+    fn picks_the_format_from_the_texture() {
+        let normal_map = TextureImportSettings { srgb: false, normal_map: true, mipmaps: false, ..Default::default() };
+        let file = cache("bc5", 8, 8, [128, 128, 255, 255], normal_map);
+        assert_eq!(ktx2::Reader::new(&file[..]).unwrap().header().format, Some(ktx2::Format::BC5_UNORM_BLOCK));
+        let image = Image::from_buffer(&file, ImageType::Extension("ktx2"), CompressedImageFormats::BC, false).unwrap();
+        // Bevy rebuilds the blue of two channel normal maps
+        assert_eq!(image.texture_descriptor.format, TextureFormat::Bc5RgUnorm);
+
+        let transparent = TextureImportSettings { mipmaps: false, ..Default::default() };
+        let file = cache("bc3", 4, 4, [255, 255, 255, 128], transparent);
+        let reader = ktx2::Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.header().format, Some(ktx2::Format::BC3_SRGB_BLOCK));
+        assert_eq!(level_index(&file, 1)[0][2], 16);
+
+        // sides that aren't multiples of 4 can't be block compressed
+        let file = cache("rgba8", 6, 3, [1, 2, 3, 255], transparent);
+        let reader = ktx2::Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.header().format, Some(ktx2::Format::R8G8B8A8_SRGB));
+        assert_eq!(level_index(&file, 1)[0][2], 6 * 3 * 4);
+    }
+}
@@ -0,0 +1,44 @@
+//! Converts textures in formats the viewer can't load: TGA, TIFF, BMP and PSD into PNG,
+//! and OpenEXR into Radiance HDR, which keeps the values above 1.
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::codecs::hdr::HdrEncoder;
+use image::DynamicImage;
+
+use crate::psd::read_psd;
+
+/// Extension a texture with `extension` is converted to, `None` when the viewer loads it as it is.
+pub fn converted_extension(extension: &str) -> Option<&'static str> {
+    match extension.to_ascii_lowercase().as_str() {
+        "tga" | "tif" | "tiff" | "bmp" | "psd" => Some("png"),
+        "exr" => Some("hdr"),
+        _ => None,
+    }
+}
+
+/// Open a texture of the export, in any of the formats the importer reads.
+pub fn open_texture(path: &Path) -> Result<DynamicImage, Box<dyn Error>> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some(extension) if extension.eq_ignore_ascii_case("psd") => read_psd(&std::fs::read(path)?),
+        _ => Ok(image::open(path)?),
+    }
+}
+
+/// Convert the texture at `source` into the format of `destination`'s extension.
+pub fn convert_texture(source: &Path, destination: &Path) -> Result<(), Box<dyn Error>> {
+    let image = open_texture(source)?;
+    match destination.extension().and_then(OsStr::to_str) {
+        Some("hdr") => {
+            let image = image.into_rgb32f();
+            let pixels: Vec<_> = image.pixels().copied().collect();
+            let file = BufWriter::new(File::create(destination)?);
+            HdrEncoder::new(file).encode(&pixels, image.width() as usize, image.height() as usize)?;
+        }
+        _ => image.save(destination)?,
+    }
+    Ok(())
+}
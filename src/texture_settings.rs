@@ -0,0 +1,218 @@
+//! The `TextureImporter` settings of a texture's `.meta` file, so the viewer samples it like Unity does.
+use std::error::Error;
+use std::path::Path;
+
+use serde_yaml::Value;
+
+use crate::unity_yaml::{parse_documents, value_to_i64};
+
+/// `textureType` of textures imported as normal maps.
+const NORMAL_MAP_TYPE: i64 = 1;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct TextureImportSettings {
+    /// The texture holds colors stored in sRGB, off for normal maps, masks and other data.
+    pub srgb: bool,
+    /// Imported as a normal map, `textureType: 1`.
+    pub normal_map: bool,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+    pub filter: FilterMode,
+    /// Anisotropic filtering level, 0 and 1 are off.
+    pub aniso: u16,
+    pub mipmaps: bool,
+    /// Largest width or height Unity imports the texture at.
+    pub max_size: u32,
+}
+
+impl Default for TextureImportSettings {
+    fn default() -> Self {
+        TextureImportSettings {
+            srgb: true,
+            normal_map: false,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+            filter: FilterMode::Bilinear,
+            aniso: 1,
+            mipmaps: true,
+            max_size: 2048,
+        }
+    }
+}
+
+/// `wrapU` / `wrapV` of the importer.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+    /// Mirrored once around the origin, then clamped.
+    MirrorOnce,
+}
+
+impl WrapMode {
+    fn from_unity(value: i64) -> Option<WrapMode> {
+        match value {
+            0 => Some(WrapMode::Repeat),
+            1 => Some(WrapMode::Clamp),
+            2 => Some(WrapMode::Mirror),
+            3 => Some(WrapMode::MirrorOnce),
+            _ => None,
+        }
+    }
+}
+
+/// `filterMode` of the importer.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Point,
+    Bilinear,
+    Trilinear,
+}
+
+impl TextureImportSettings {
+    /// Read the settings of the texture at `texture` from the `.meta` file next to it,
+    /// `None` when there is none or it isn't a texture's.
+    pub fn from_texture<P: AsRef<Path>>(texture: P) -> Result<Option<TextureImportSettings>, Box<dyn Error>> {
+        let mut meta = texture.as_ref().as_os_str().to_owned();
+        meta.push(".meta");
+        match std::fs::read_to_string(meta) {
+            Ok(text) => TextureImportSettings::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    /// Parse the text of a `.meta` file, `None` when its importer isn't a `TextureImporter`.
+    ///
+    /// -1 leaves a setting to Unity's default. Unity 5.4 and older have `linearTexture`
+    /// instead of `sRGBTexture`, and a single `wrapMode` instead of `wrapU` and `wrapV`.
+    pub fn parse(text: &str) -> Result<Option<TextureImportSettings>, Box<dyn Error>> {
+        let documents = parse_documents(text)?;
+        let Some(importer) = documents.first().and_then(|document| document.body.get("TextureImporter")) else {
+            return Ok(None);
+        };
+        let int = |value: &Value, key: &str| value.get(key).and_then(value_to_i64).filter(|value| *value != -1);
+        let mipmaps = importer.get("mipmaps").unwrap_or(&Value::Null);
+        let texture_settings = importer.get("textureSettings").unwrap_or(&Value::Null);
+        let wrap = |key: &str| {
+            int(texture_settings, key)
+                .or_else(|| int(texture_settings, "wrapMode"))
+                .and_then(WrapMode::from_unity)
+                .unwrap_or(WrapMode::Repeat)
+        };
+        let default = TextureImportSettings::default();
+
+        let normal_map = int(importer, "textureType") == Some(NORMAL_MAP_TYPE);
+        // Unity imports normal maps as linear whatever sRGBTexture says
+        let srgb = match (int(mipmaps, "sRGBTexture"), int(mipmaps, "linearTexture")) {
+            _ if normal_map => false,
+            (Some(srgb), _) => srgb != 0,
+            (None, Some(linear)) => linear == 0,
+            (None, None) => default.srgb,
+        };
+        let filter = match int(texture_settings, "filterMode") {
+            Some(0) => FilterMode::Point,
+            Some(2) => FilterMode::Trilinear,
+            _ => FilterMode::Bilinear,
+        };
+        Ok(Some(TextureImportSettings {
+            srgb,
+            normal_map,
+            wrap_u: wrap("wrapU"),
+            wrap_v: wrap("wrapV"),
+            filter,
+            aniso: int(texture_settings, "aniso").map_or(default.aniso, |aniso| aniso.clamp(0, 16) as u16),
+            mipmaps: int(mipmaps, "enableMipMap").map_or(default.mipmaps, |enabled| enabled != 0),
+            max_size: int(importer, "maxTextureSize")
+                .filter(|size| *size > 0)
+                .map_or(default.max_size, |size| size as u32),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> TextureImportSettings {
+        TextureImportSettings::parse(text).unwrap().unwrap()
+    }
+
+    #[test]
+    fn reads_real_metas() {
+        let albedo = TextureImportSettings::from_texture("assets/textures/T_Apple_01_A_ALB.png").unwrap().unwrap();
+        assert_eq!(albedo, TextureImportSettings::default());
+
+        let normal = std::fs::read_to_string("assets/textures/T_Apple_01_A_NRM.png.meta").unwrap();
+        let expected = TextureImportSettings {
+            srgb: false,
+            normal_map: true,
+            ..Default::default()
+        };
+        assert_eq!(parse(&normal), expected);
+        // normal maps are linear whatever sRGBTexture says
+        assert_eq!(parse(&normal.replace("sRGBTexture: 0", "sRGBTexture: 1")), expected);
+    }
+
+    #[test]
+    fn reads_legacy_keys() {
+        let text = r#"fileFormatVersion: 2
+guid: 0123456789abcdef0123456789abcdef
+TextureImporter:
+  serializedVersion: 2
+  mipmaps:
+    enableMipMap: 0
+    linearTexture: 1
+  textureSettings:
+    filterMode: 0
+    aniso: 4
+    wrapMode: 1
+  maxTextureSize: 512
+"#;
+        let settings = parse(text);
+        assert!(!settings.srgb && !settings.normal_map && !settings.mipmaps);
+        assert_eq!((settings.wrap_u, settings.wrap_v), (WrapMode::Clamp, WrapMode::Clamp));
+        assert_eq!((settings.filter, settings.aniso, settings.max_size), (FilterMode::Point, 4, 512));
+        // wrapU and wrapV win over wrapMode
+        let settings = parse(&text.replace("    wrapMode: 1", "    wrapMode: 1\n    wrapU: 2\n    wrapV: -1"));
+        assert_eq!((settings.wrap_u, settings.wrap_v), (WrapMode::Mirror, WrapMode::Clamp));
+        assert!(parse(&text.replace("linearTexture: 1", "linearTexture: 0")).srgb);
+    }
+
+    #[test]
+    fn unset_values_fall_back_to_defaults() {
+        let text = r#"TextureImporter:
+  mipmaps:
+    enableMipMap: -1
+    sRGBTexture: -1
+  textureSettings:
+    filterMode: -1
+    aniso: -1
+    wrapU: -1
+    wrapV: -1
+  maxTextureSize: -1
+  textureType: -1
+"#;
+        assert_eq!(parse(text), TextureImportSettings::default());
+        assert_eq!(parse("TextureImporter: {}\n"), TextureImportSettings::default());
+    }
+
+    #[test]
+    fn clamps_aniso() {
+        let text = |aniso: i64| format!("TextureImporter:\n  textureSettings:\n    aniso: {}\n    filterMode: 2\n", aniso);
+        assert_eq!(parse(&text(9)).aniso, 9);
+        assert_eq!(parse(&text(64)).aniso, 16);
+        assert_eq!(parse(&text(-5)).aniso, 0);
+        assert_eq!(parse(&text(9)).filter, FilterMode::Trilinear);
+    }
+
+    #[test]
+    fn ignores_other_importers() {
+        let text = "fileFormatVersion: 2\nguid: 0123456789abcdef0123456789abcdef\nNativeFormatImporter:\n  mainObjectFileID: 2100000\n";
+        assert_eq!(TextureImportSettings::parse(text).unwrap(), None);
+        let material = std::fs::read_to_string("assets/materials/M_Apple_01_A.mat.meta").unwrap();
+        assert_eq!(TextureImportSettings::parse(&material).unwrap(), None);
+        assert_eq!(TextureImportSettings::from_texture("assets/textures/missing.png").unwrap(), None);
+    }
+}
@@ -0,0 +1,475 @@
+//! Reader for the YAML dialect Unity uses for serialized assets (`.mat`, `.prefab`, `.asset`, `.meta`).
+//!
+//! Unity files are a stream of documents introduced by `--- !u!<classID> &<fileID>` headers
+//! (optionally followed by `stripped`), with a `%TAG !u! tag:unity3d.com,2011:` directive at the top.
+//! The headers are handled here, and the body of each document is handed to `serde_yaml`.
+use std::error::Error;
+use std::path::Path;
+
+use serde_yaml::{Mapping, Value};
+
+/// Class ID of `Material` documents.
+pub const MATERIAL_CLASS_ID: i64 = 21;
+/// fileID of the material a `.mat` file holds as its main object.
+const MAIN_MATERIAL_FILE_ID: i64 = 2100000;
+
+/// One `--- !u!<class_id> &<file_id>` document of a Unity YAML file.
+#[derive(Debug, Clone)]
+pub struct UnityDocument {
+    pub class_id: i64,
+    pub file_id: i64,
+    pub stripped: bool,
+    /// The type name of the root key, e.g. `Material` or `GameObject`.
+    pub type_name: String,
+    /// The value stored under the root key.
+    pub body: Value,
+}
+
+/// Reference to another object, written by Unity as `{fileID: 2800000, guid: ..., type: 3}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileRef {
+    pub file_id: i64,
+    pub guid: Option<String>,
+    pub ref_type: Option<i64>,
+}
+
+impl FileRef {
+    pub fn from_value(value: &Value) -> Option<FileRef> {
+        let file_id = value.get("fileID").and_then(value_to_i64)?;
+        Some(FileRef {
+            file_id,
+            guid: value.get("guid").and_then(value_to_string),
+            ref_type: value.get("type").and_then(value_to_i64),
+        })
+    }
+    /// A reference is null when it has neither a fileID nor a guid, as in `{fileID: 0}`.
+    pub fn is_null(&self) -> bool {
+        self.file_id == 0 && self.guid.is_none()
+    }
+}
+
+/// A texture slot from `m_SavedProperties.m_TexEnvs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexEnv {
+    pub texture: FileRef,
+    pub scale: [f32; 2],
+    pub offset: [f32; 2],
+}
+
+/// The parts of a Unity `Material` the importer cares about.
+#[derive(Debug, Clone, Default)]
+pub struct UnityMaterial {
+    pub name: String,
+    pub shader: FileRef,
+    pub keywords: Vec<String>,
+    pub tex_envs: Vec<(String, TexEnv)>,
+    pub floats: Vec<(String, f32)>,
+    pub colors: Vec<(String, [f32; 4])>,
+}
+
+impl UnityMaterial {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<UnityMaterial, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        UnityMaterial::parse(&text)
+    }
+    pub fn parse(text: &str) -> Result<UnityMaterial, Box<dyn Error>> {
+        let documents = parse_documents(text)?;
+        let is_material = |document: &&UnityDocument| document.class_id == MATERIAL_CLASS_ID || document.type_name == "Material";
+        // the main object first, other materials are sub-assets stored alongside it
+        let document = documents
+            .iter()
+            .filter(is_material)
+            .find(|document| document.file_id == MAIN_MATERIAL_FILE_ID)
+            .or_else(|| documents.iter().find(is_material))
+            .ok_or("no Material document found")?;
+        UnityMaterial::from_document(document)
+    }
+    pub fn from_document(document: &UnityDocument) -> Result<UnityMaterial, Box<dyn Error>> {
+        let body = &document.body;
+        let name = body.get("m_Name").and_then(value_to_string).unwrap_or_default();
+        let shader = body.get("m_Shader").and_then(FileRef::from_value).unwrap_or_default();
+
+        // Unity < 2021.2 writes a space separated string, newer versions write lists of valid and invalid keywords
+        let mut keywords: Vec<String> = body
+            .get("m_ShaderKeywords")
+            .and_then(value_to_string)
+            .map(|keywords| keywords.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
+        for key in ["m_ValidKeywords", "m_InvalidKeywords"] {
+            if let Some(Value::Sequence(list)) = body.get(key) {
+                keywords.extend(list.iter().filter_map(value_to_string));
+            }
+        }
+
+        let properties = body.get("m_SavedProperties");
+        let entries = |key: &str| -> Vec<(String, Value)> {
+            match properties.and_then(|properties| properties.get(key)) {
+                Some(Value::Sequence(list)) => list.iter().filter_map(property_entry).collect(),
+                _ => Vec::new(),
+            }
+        };
+
+        let tex_envs = entries("m_TexEnvs")
+            .into_iter()
+            .map(|(name, value)| {
+                let texture = value.get("m_Texture").and_then(FileRef::from_value).unwrap_or_default();
+                let scale = value.get("m_Scale").and_then(value_to_vec2).unwrap_or([1.0, 1.0]);
+                let offset = value.get("m_Offset").and_then(value_to_vec2).unwrap_or([0.0, 0.0]);
+                (name, TexEnv { texture, scale, offset })
+            })
+            .collect();
+        // m_Ints only exists on Unity 2021+, the shader sees them as floats anyway
+        let floats = entries("m_Floats")
+            .into_iter()
+            .chain(entries("m_Ints"))
+            .filter_map(|(name, value)| Some((name, value_to_f64(&value)? as f32)))
+            .collect();
+        let colors = entries("m_Colors")
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value_to_color(&value)?)))
+            .collect();
+
+        Ok(UnityMaterial {
+            name,
+            shader,
+            keywords,
+            tex_envs,
+            floats,
+            colors,
+        })
+    }
+    pub fn tex_env(&self, name: &str) -> Option<&TexEnv> {
+        self.tex_envs.iter().find(|(n, _)| n == name).map(|(_, tex_env)| tex_env)
+    }
+    pub fn float(&self, name: &str) -> Option<f32> {
+        self.floats.iter().find(|(n, _)| n == name).map(|(_, value)| *value)
+    }
+    pub fn color(&self, name: &str) -> Option<[f32; 4]> {
+        self.colors.iter().find(|(n, _)| n == name).map(|(_, value)| *value)
+    }
+    pub fn has_keyword(&self, keyword: &str) -> bool {
+        self.keywords.iter().any(|k| k == keyword)
+    }
+}
+
+/// Split a Unity YAML file into its documents.
+///
+/// Text that has no `---` header at all (such as a `.meta` file) is returned as a single document
+/// with a class and file ID of 0.
+pub fn parse_documents(text: &str) -> Result<Vec<UnityDocument>, Box<dyn Error>> {
+    let mut documents = Vec::new();
+    let mut header: Option<(i64, i64, bool)> = None;
+    let mut body = String::new();
+
+    for line in text.lines() {
+        if line.starts_with('%') {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("---") {
+            if header.is_some() || !body.trim().is_empty() {
+                documents.push(build_document(header.take(), &body)?);
+            }
+            body.clear();
+            header = Some(parse_header(rest)?);
+            continue;
+        }
+        body.push_str(&quote_guids(line));
+        body.push('\n');
+    }
+    if header.is_some() || !body.trim().is_empty() {
+        documents.push(build_document(header, &body)?);
+    }
+    Ok(documents)
+}
+
+fn parse_header(header: &str) -> Result<(i64, i64, bool), Box<dyn Error>> {
+    let mut class_id = 0;
+    let mut file_id = 0;
+    let mut stripped = false;
+    for token in header.split_whitespace() {
+        if let Some(class) = token.strip_prefix("!u!") {
+            class_id = class.parse()?;
+        } else if let Some(id) = token.strip_prefix('&') {
+            file_id = id.parse()?;
+        } else if token == "stripped" {
+            stripped = true;
+        }
+    }
+    Ok((class_id, file_id, stripped))
+}
+
+fn build_document(header: Option<(i64, i64, bool)>, body: &str) -> Result<UnityDocument, Box<dyn Error>> {
+    let (class_id, file_id, stripped) = header.unwrap_or((0, 0, false));
+    let value: Value = if body.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_yaml::from_str(body)
+            .map_err(|e| format!("invalid YAML in document &{}: {}", file_id, e))?
+    };
+    // Unity documents are a mapping with a single key naming the type
+    let (type_name, body) = match value {
+        Value::Mapping(mapping) if mapping.len() == 1 && class_id != 0 => {
+            let (key, value) = mapping.into_iter().next().unwrap();
+            (value_to_string(&key).unwrap_or_default(), value)
+        }
+        value => (String::new(), value),
+    };
+    Ok(UnityDocument {
+        class_id,
+        file_id,
+        stripped,
+        type_name,
+        body,
+    })
+}
+
+/// GUIDs are 32 hex digits, so YAML may read ones like `0000000000000000e000000000000000` as numbers.
+/// Quote every `guid:` value before handing the text to serde_yaml so they always stay strings.
+fn quote_guids(line: &str) -> String {
+    let mut output = String::with_capacity(line.len() + 8);
+    let mut rest = line;
+    while let Some(index) = rest.find("guid: ") {
+        let (before, after) = rest.split_at(index + "guid: ".len());
+        output.push_str(before);
+        let end = after.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(after.len());
+        if end > 0 {
+            output.push('\'');
+            output.push_str(&after[..end]);
+            output.push('\'');
+        }
+        rest = &after[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// `m_SavedProperties` entries are `- _Name: value` in serializedVersion 3,
+/// and `- first: {name: _Name}\n  second: value` in older versions.
+fn property_entry(entry: &Value) -> Option<(String, Value)> {
+    let mapping: &Mapping = entry.as_mapping()?;
+    if let (Some(first), Some(second)) = (mapping.get("first"), mapping.get("second")) {
+        let name = first.get("name").and_then(value_to_string)?;
+        return Some((name, second.clone()));
+    }
+    let (key, value) = mapping.iter().next()?;
+    Some((value_to_string(key)?, value.clone()))
+}
+
+pub fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+pub fn value_to_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(number) => number.as_i64().or_else(|| number.as_u64().map(|n| n as i64)),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    }
+}
+
+pub fn value_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    }
+}
+
+fn value_to_vec2(value: &Value) -> Option<[f32; 2]> {
+    Some([
+        value.get("x").and_then(value_to_f64)? as f32,
+        value.get("y").and_then(value_to_f64)? as f32,
+    ])
+}
+
+fn value_to_color(value: &Value) -> Option<[f32; 4]> {
+    Some([
+        value.get("r").and_then(value_to_f64)? as f32,
+        value.get("g").and_then(value_to_f64)? as f32,
+        value.get("b").and_then(value_to_f64)? as f32,
+        value.get("a").and_then(value_to_f64).unwrap_or(1.0) as f32,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATERIAL: &str = r#"%YAML 1.1
+%TAG !u! tag:unity3d.com,2011:
+--- !u!21 &2100000
+Material:
+  m_Name: M_Test_01
+  m_Shader: {fileID: 4800000, guid: 6e4ae4064600d784cac1e41a9e6f2e59, type: 3}
+  m_ShaderKeywords: _MASKMAP _NORMALMAP
+  m_SavedProperties:
+    serializedVersion: 3
+    m_TexEnvs:
+    - _BaseColorMap:
+        m_Texture: {fileID: 2800000, guid: 4ec7343fe6ea0dd4799a2a8003c8c913, type: 3}
+        m_Scale: {x: 4, y: 2}
+        m_Offset: {x: 0.25, y: 0.5}
+    - _DetailMap:
+        m_Texture: {fileID: 0}
+        m_Scale: {x: 1, y: 1}
+        m_Offset: {x: 0, y: 0}
+    m_Floats:
+    - _Metallic: 0.5
+    m_Colors:
+    - _BaseColor: {r: 1, g: 0.5, b: 0.25, a: 1}
+"#;
+
+    /// A built-in shader with type 0, a texture with type 2 and its keys in another order,
+    /// GUIDs YAML reads as numbers, and no `m_Floats` or `m_Colors`.
+    const MATERIAL_REFERENCES: &str = r#"%YAML 1.1
+%TAG !u! tag:unity3d.com,2011:
+--- !u!21 &2100000
+Material:
+  m_Name: M_Test_02
+  m_Shader: {fileID: 46, guid: 0000000000000000f000000000000000, type: 0}
+  m_SavedProperties:
+    m_TexEnvs:
+    - _MainTex:
+        m_Texture: {type: 2, guid: 12345678901234567890123456789012, fileID: 10309}
+"#;
+
+    /// serializedVersion 2 writes the properties as `first`/`second` pairs.
+    const MATERIAL_LEGACY: &str = r#"%YAML 1.1
+%TAG !u! tag:unity3d.com,2011:
+--- !u!21 &2100000
+Material:
+  m_Name: M_Test_03
+  m_Shader: {fileID: 4800000, guid: 933532a4fcc9baf4fa0491de14d08ed7, type: 3}
+  m_SavedProperties:
+    serializedVersion: 2
+    m_TexEnvs:
+    - first:
+        name: _MainTex
+      second:
+        m_Texture: {fileID: 2800000, guid: 4ec7343fe6ea0dd4799a2a8003c8c913, type: 3}
+        m_Scale: {x: 2, y: 2}
+        m_Offset: {x: 0, y: 0}
+    m_Floats:
+    - first:
+        name: _Glossiness
+      second: 0.25
+    m_Ints:
+    - _QueueOffset: 1
+    m_Colors:
+    - first:
+        name: _Color
+      second: {r: 0, g: 1, b: 0}
+"#;
+
+    #[test]
+    fn parse_documents_reads_headers() {
+        let text = r#"%YAML 1.1
+%TAG !u! tag:unity3d.com,2011:
+--- !u!1 &100
+GameObject:
+  m_Name: Apple
+--- !u!4 &200 stripped
+Transform:
+  m_CorrespondingSourceObject: {fileID: 0}
+"#;
+        let documents = parse_documents(text).unwrap();
+        assert_eq!(documents.len(), 2);
+        assert_eq!((documents[0].class_id, documents[0].file_id, documents[0].stripped), (1, 100, false));
+        assert_eq!(documents[0].type_name, "GameObject");
+        assert_eq!(documents[0].body.get("m_Name").and_then(value_to_string).as_deref(), Some("Apple"));
+        assert_eq!((documents[1].class_id, documents[1].file_id, documents[1].stripped), (4, 200, true));
+        assert_eq!(documents[1].type_name, "Transform");
+    }
+
+    #[test]
+    fn parse_documents_reads_text_without_header() {
+        let documents = parse_documents("fileFormatVersion: 2\nguid: 4ec7343fe6ea0dd4799a2a8003c8c913\n").unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!((documents[0].class_id, documents[0].file_id), (0, 0));
+        assert_eq!(documents[0].type_name, "");
+        let guid = documents[0].body.get("guid").and_then(value_to_string);
+        assert_eq!(guid.as_deref(), Some("4ec7343fe6ea0dd4799a2a8003c8c913"));
+    }
+
+    #[test]
+    fn quote_guids_keeps_guids_strings() {
+        assert_eq!(
+            quote_guids("  m_Shader: {fileID: 46, guid: 0000000000000000f000000000000000, type: 0}"),
+            "  m_Shader: {fileID: 46, guid: '0000000000000000f000000000000000', type: 0}"
+        );
+        assert_eq!(
+            quote_guids("- {guid: 12345678901234567890123456789012}, {guid: 1e5}"),
+            "- {guid: '12345678901234567890123456789012'}, {guid: '1e5'}"
+        );
+        // already quoted, or no GUID after the key
+        assert_eq!(quote_guids("guid: 'abc'"), "guid: 'abc'");
+        assert_eq!(quote_guids("m_Name: guid"), "m_Name: guid");
+    }
+
+    #[test]
+    fn parse_reads_material() {
+        let material = UnityMaterial::parse(MATERIAL).unwrap();
+        assert_eq!(material.name, "M_Test_01");
+        assert_eq!(material.shader.file_id, 4800000);
+        assert_eq!(material.shader.guid.as_deref(), Some("6e4ae4064600d784cac1e41a9e6f2e59"));
+        assert_eq!(material.keywords, ["_MASKMAP", "_NORMALMAP"]);
+        let base_color = material.tex_env("_BaseColorMap").unwrap();
+        assert_eq!(base_color.texture.guid.as_deref(), Some("4ec7343fe6ea0dd4799a2a8003c8c913"));
+        assert_eq!((base_color.scale, base_color.offset), ([4.0, 2.0], [0.25, 0.5]));
+        assert!(material.tex_env("_DetailMap").unwrap().texture.is_null());
+        assert_eq!(material.float("_Metallic"), Some(0.5));
+        assert_eq!(material.color("_BaseColor"), Some([1.0, 0.5, 0.25, 1.0]));
+    }
+
+    #[test]
+    fn parse_reads_references_in_any_form() {
+        let material = UnityMaterial::parse(MATERIAL_REFERENCES).unwrap();
+        let shader = FileRef {
+            file_id: 46,
+            guid: Some("0000000000000000f000000000000000".to_string()),
+            ref_type: Some(0),
+        };
+        assert_eq!(material.shader, shader);
+        let main_tex = material.tex_env("_MainTex").unwrap();
+        let texture = FileRef {
+            file_id: 10309,
+            guid: Some("12345678901234567890123456789012".to_string()),
+            ref_type: Some(2),
+        };
+        assert_eq!(main_tex.texture, texture);
+        assert_eq!((main_tex.scale, main_tex.offset), ([1.0, 1.0], [0.0, 0.0]));
+        assert!(material.floats.is_empty());
+        assert!(material.colors.is_empty());
+    }
+
+    #[test]
+    fn parse_reads_legacy_entries() {
+        let material = UnityMaterial::parse(MATERIAL_LEGACY).unwrap();
+        let main_tex = material.tex_env("_MainTex").unwrap();
+        assert_eq!(main_tex.texture.guid.as_deref(), Some("4ec7343fe6ea0dd4799a2a8003c8c913"));
+        assert_eq!(main_tex.scale, [2.0, 2.0]);
+        assert_eq!(material.float("_Glossiness"), Some(0.25));
+        assert_eq!(material.float("_QueueOffset"), Some(1.0));
+        // a color without alpha is opaque
+        assert_eq!(material.color("_Color"), Some([0.0, 1.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn parse_prefers_main_material() {
+        let sub_asset = MATERIAL_REFERENCES.replace("&2100000", "&-8760981346238468126");
+        let text = format!("{}{}", sub_asset, MATERIAL.split_once("--- ").map(|(_, main)| format!("--- {}", main)).unwrap());
+        assert_eq!(UnityMaterial::parse(&text).unwrap().name, "M_Test_01");
+        assert_eq!(UnityMaterial::parse(&sub_asset).unwrap().name, "M_Test_02");
+    }
+
+    #[test]
+    fn parse_fails_without_material() {
+        let text = "%YAML 1.1\n--- !u!1 &100\nGameObject:\n  m_Name: Apple\n";
+        assert!(UnityMaterial::parse(text).is_err());
+    }
+}